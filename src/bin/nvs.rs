@@ -1,8 +1,14 @@
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
+use serde_json::{json, Value};
 
-use esp32::nvs::Nvs;
+use std::fmt::Write;
+use std::path::Path;
 
-const VERSION: &'static str = "0.1.0";
+use esp32::nvs::event::{Entry, EntryType};
+use esp32::nvs::{parse_csv, parse_value, CrcPolicy, Nvs, NvsEditor, NvsGenerator, Version};
+use esp32::partition_table::parse_int;
+
+const VERSION: &str = "0.1.0";
 
 fn main() {
     let app = App::new("nvs")
@@ -79,6 +85,17 @@ fn main() {
                 .long("deleted")
                 .help("Interact with deleted entries"),
         )
+        .arg(
+            Arg::with_name("crc")
+                .long("crc")
+                .help("How to handle pages and entries with an invalid crc")
+                .value_name("POLICY")
+                .possible_value("skip")
+                .possible_value("report")
+                .possible_value("error")
+                .takes_value(true)
+                .default_value("skip"),
+        )
         .arg(
             Arg::with_name("output")
                 .short("o")
@@ -92,69 +109,451 @@ fn main() {
                 .takes_value(true)
                 .default_value("text"),
         )
+        .arg(
+            Arg::with_name("generate")
+                .long("generate")
+                .help("Generate FILE from a csv in the nvs_partition_gen.py format")
+                .value_name("CSV")
+                .takes_value(true)
+                .conflicts_with_all(&["namespaces", "keys", "values", "pairs", "entries"]),
+        )
+        .arg(
+            Arg::with_name("size")
+                .long("size")
+                .help("Size of the generated partition")
+                .value_name("SIZE")
+                .takes_value(true)
+                .default_value("0x6000"),
+        )
+        .arg(
+            Arg::with_name("nvs-version")
+                .long("nvs-version")
+                .help("Page format version of the generated partition")
+                .value_name("VERSION")
+                .possible_value("1")
+                .possible_value("2")
+                .takes_value(true)
+                .default_value("2"),
+        )
+        .arg(
+            Arg::with_name("set")
+                .long("set")
+                .help("Write KEY in the namespace given by --namespace")
+                .value_name("KEY")
+                .takes_value(true)
+                .requires_all(&["namespace", "value"])
+                .conflicts_with_all(&["generate", "erase", "erase-namespace"]),
+        )
+        .arg(
+            Arg::with_name("type")
+                .long("type")
+                .help("Encoding of the value written by --set")
+                .value_name("TYPE")
+                .possible_values(&[
+                    "u8", "i8", "u16", "i16", "u32", "i32", "u64", "i64", "string", "hex2bin",
+                    "base64",
+                ])
+                .takes_value(true)
+                .default_value("string"),
+        )
+        .arg(
+            Arg::with_name("value")
+                .long("value")
+                .help("Value written by --set")
+                .value_name("VALUE")
+                .takes_value(true)
+                .allow_hyphen_values(true),
+        )
+        .arg(
+            Arg::with_name("erase")
+                .long("erase")
+                .help("Erase KEY in the namespace given by --namespace")
+                .value_name("KEY")
+                .takes_value(true)
+                .requires("namespace")
+                .conflicts_with_all(&["generate", "erase-namespace"]),
+        )
+        .arg(
+            Arg::with_name("erase-namespace")
+                .long("erase-namespace")
+                .help("Erase every key in the namespace given by --namespace")
+                .requires("namespace")
+                .conflicts_with("generate"),
+        )
         .get_matches();
 
+    if let Some(csv) = app.value_of("generate") {
+        if let Err(err) = generate(&app, csv) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    if app.is_present("set") || app.is_present("erase") || app.is_present("erase-namespace") {
+        if let Err(err) = edit(&app) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+
     let file = app.value_of("file").unwrap();
-    let nvs = Nvs::new(file, app.is_present("deleted"));
+    let crc_policy = match app.value_of("crc").unwrap() {
+        "report" => CrcPolicy::Report,
+        "error" => CrcPolicy::Error,
+        _ => CrcPolicy::Skip,
+    };
+    let nvs = match Nvs::new(file, app.is_present("deleted"), crc_policy) {
+        Ok(nvs) => nvs,
+        Err(err) => {
+            eprintln!("{}: {}", file, err);
+            std::process::exit(1);
+        }
+    };
 
-    let ns = app.value_of("namespace");
+    let entries: Vec<&Entry> = match app.value_of("namespace") {
+        Some(ns) => match nvs.namespace(ns) {
+            Some(namespace) => {
+                let mut entries: Vec<&Entry> = namespace.values().copied().collect();
+                entries.sort_by_key(|entry| (entry.page(), entry.start()));
+                entries
+            }
+            // TODO: should we let the user know the ns doesn't exist?
+            None => vec![],
+        },
+        None => nvs.entries().iter().collect(),
+    };
 
-    if app.is_present("namespaces") {
+    let format = app.value_of("output").unwrap();
+    let fields: &[&'static str] = if app.is_present("namespaces") {
         let namespaces = nvs.namespaces();
-        namespaces.iter().for_each(|ns| {
-            println!("{}", ns);
-        });
+        let records: Vec<Record> = namespaces
+            .iter()
+            .map(|ns| vec![("namespace", json!(ns))])
+            .collect();
+        print!("{}", render(format, &records));
+        return;
     } else if app.is_present("keys") {
-        if let Some(ns) = ns {
-            let namespace = nvs.namespace(ns);
-            if let Some(namespace) = namespace {
-                namespace.keys().for_each(|key| println!("{}", key));
-            }
-            // TODO: should we let the user know the ns doesn't exist?
-        } else {
-            let entries = nvs.entries();
-            entries.iter().for_each(|entry| println!("{}", entry.key()));
-        };
+        &["namespace", "key", "type"]
     } else if app.is_present("values") {
-        if let Some(ns) = ns {
-            let namespace = nvs.namespace(ns);
-            if let Some(namespace) = namespace {
-                namespace
-                    .values()
-                    .for_each(|entry| println!("{}", entry.data()));
-            }
-            // TODO: should we let the user know the ns doesn't exist?
-        } else {
-            let entries = nvs.entries();
-            entries
-                .iter()
-                .for_each(|entry| println!("{}", entry.data()));
-        };
+        &["namespace", "type", "value"]
     } else if app.is_present("pairs") {
-        if let Some(ns) = ns {
-            let namespace = nvs.namespace(ns);
-            if let Some(namespace) = namespace {
-                namespace
-                    .values()
-                    .for_each(|entry| println!("{}: {}", entry.key(), entry.data()));
+        &["namespace", "key", "type", "value"]
+    } else if app.is_present("entries") {
+        &[
+            "namespace",
+            "key",
+            "type",
+            "value",
+            "page",
+            "start",
+            "end",
+            "span",
+            "chunk_index",
+            "crc32",
+            "validity",
+        ]
+    } else {
+        return;
+    };
+
+    if format == "csv" && app.is_present("pairs") {
+        print!("{}", partition_gen_csv(&nvs, &entries));
+    } else {
+        let records: Vec<Record> = entries
+            .iter()
+            .map(|entry| record(&nvs, entry, fields))
+            .collect();
+        print!("{}", render(format, &records));
+    }
+}
+
+fn generate(app: &ArgMatches, csv: &str) -> Result<(), String> {
+    let size = app.value_of("size").unwrap();
+    let size = parse_int(size).ok_or_else(|| format!("invalid size '{}'", size))?;
+    let version = match app.value_of("nvs-version").unwrap() {
+        "1" => Version::V1,
+        _ => Version::V2,
+    };
+
+    let input = std::fs::read_to_string(csv).map_err(|err| format!("{}: {}", csv, err))?;
+    let base_dir = Path::new(csv).parent().unwrap_or_else(|| Path::new(""));
+    let items = parse_csv(&input, base_dir).map_err(|err| format!("{}: {}", csv, err))?;
+
+    let mut generator = NvsGenerator::new(size as usize).version(version);
+    generator.extend(items);
+    let data = generator.generate().map_err(|err| err.to_string())?;
+
+    let file = app.value_of("file").unwrap();
+    std::fs::write(file, data).map_err(|err| format!("{}: {}", file, err))
+}
+
+/// The fields of an entry in the order they are output
+type Record = Vec<(&'static str, Value)>;
+
+fn record(nvs: &Nvs, entry: &Entry, fields: &[&'static str]) -> Record {
+    fields
+        .iter()
+        .map(|field| {
+            let value = match *field {
+                "namespace" => json!(nvs.namespace_name(entry.ns())),
+                "key" => json!(entry.key()),
+                "type" => json!(entry.data().type_name()),
+                "value" => value_json(entry.data()),
+                "page" => json!(entry.page()),
+                "start" => json!(entry.start()),
+                "end" => json!(entry.end()),
+                "span" => json!(entry.span()),
+                "chunk_index" => json!(entry.chunk_index()),
+                "crc32" => json!(entry.crc32()),
+                "validity" => json!(format!("{:?}", entry.validity())),
+                _ => Value::Null,
+            };
+            (*field, value)
+        })
+        .collect()
+}
+
+/// Blobs are hex encoded so they can be read back losslessly
+fn value_json(data: &EntryType) -> Value {
+    match data {
+        EntryType::U8(val) => json!(val),
+        EntryType::I8(val) => json!(val),
+        EntryType::U16(val) => json!(val),
+        EntryType::I16(val) => json!(val),
+        EntryType::U32(val) => json!(val),
+        EntryType::I32(val) => json!(val),
+        EntryType::U64(val) => json!(val),
+        EntryType::I64(val) => json!(val),
+        EntryType::String(val) => json!(val),
+        EntryType::Blob(val) | EntryType::BlobData(val) => json!(hex(val)),
+        EntryType::BlobIndex {
+            size,
+            chunk_count,
+            chunk_start,
+        } => json!({
+            "size": size,
+            "chunk_count": chunk_count,
+            "chunk_start": chunk_start,
+        }),
+        EntryType::Any => Value::Null,
+    }
+}
+
+fn render(format: &str, records: &[Record]) -> String {
+    let mut output = String::new();
+    match format {
+        "json" => {
+            let records: Vec<Value> = records
+                .iter()
+                .map(|record| {
+                    let map: serde_json::Map<String, Value> = record
+                        .iter()
+                        .map(|(field, value)| (field.to_string(), value.clone()))
+                        .collect();
+                    Value::Object(map)
+                })
+                .collect();
+            output = serde_json::to_string_pretty(&records).unwrap();
+            output.push('\n');
+        }
+        // json scalars and objects are valid yaml flow values
+        "yaml" => {
+            for record in records {
+                for (i, (field, value)) in record.iter().enumerate() {
+                    let prefix = if i == 0 { "- " } else { "  " };
+                    let _ = writeln!(output, "{}{}: {}", prefix, field, value);
+                }
             }
-            // TODO: should we let the user know the ns doesn't exist?
-        } else {
-            let entries = nvs.entries();
-            entries
+            if records.is_empty() {
+                output.push_str("[]\n");
+            }
+        }
+        // columns padded to the widest value, the last one is left as is
+        "text" => {
+            let rows: Vec<Vec<String>> = records
                 .iter()
-                .for_each(|entry| println!("{}: {}", entry.key(), entry.data()));
-        };
-    } else if app.is_present("entries") {
-        if let Some(ns) = ns {
-            let namespace = nvs.namespace(ns);
-            if let Some(namespace) = namespace {
-                namespace.values().for_each(|entry| println!("{:?}", entry));
+                .map(|record| record.iter().map(|(_, value)| text_field(value)).collect())
+                .collect();
+            let mut widths = vec![];
+            for row in &rows {
+                widths.resize(widths.len().max(row.len()), 0);
+                for (width, field) in widths.iter_mut().zip(row) {
+                    *width = (*width).max(field.chars().count());
+                }
             }
-            // TODO: should we let the user know the ns doesn't exist?
-        } else {
-            let entries = nvs.entries();
-            entries.iter().for_each(|entry| println!("{:?}", entry));
+            for row in rows {
+                let mut line = String::new();
+                for (i, field) in row.iter().enumerate() {
+                    if i + 1 == row.len() {
+                        line.push_str(field);
+                    } else {
+                        let _ = write!(line, "{:width$}  ", field, width = widths[i]);
+                    }
+                }
+                let _ = writeln!(output, "{}", line.trim_end());
+            }
+        }
+        _ => {
+            if let Some(record) = records.first() {
+                let header: Vec<&str> = record.iter().map(|(field, _)| *field).collect();
+                let _ = writeln!(output, "{}", header.join(","));
+            }
+            for record in records {
+                let row: Vec<String> = record.iter().map(|(_, value)| csv_field(value)).collect();
+                let _ = writeln!(output, "{}", row.join(","));
+            }
+        }
+    }
+
+    output
+}
+
+/// Key value pairs as a csv in the nvs_partition_gen.py format so they can be
+/// used to generate a new partition with `--generate`
+fn partition_gen_csv(nvs: &Nvs, entries: &[&Entry]) -> String {
+    let mut output = String::from("key,type,encoding,value\n");
+    let mut namespace = None;
+    for entry in entries {
+        if namespace != Some(entry.ns()) {
+            namespace = Some(entry.ns());
+            let name = nvs.namespace_name(entry.ns()).unwrap_or_default();
+            let _ = writeln!(output, "{},namespace,,", csv_field(&json!(name)));
+        }
+
+        let encoding = match entry.data() {
+            EntryType::Blob(_) | EntryType::BlobData(_) => "hex2bin",
+            data => data.type_name(),
         };
+        let _ = writeln!(
+            output,
+            "{},data,{},{}",
+            csv_field(&json!(entry.key())),
+            encoding,
+            csv_field(&value_json(entry.data()))
+        );
+    }
+
+    output
+}
+
+/// Quote a csv field when it contains a separator, quote or line break
+fn csv_field(value: &Value) -> String {
+    let field = text_field(value);
+
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field
+    }
+}
+
+fn text_field(value: &Value) -> String {
+    match value {
+        Value::String(val) => val.clone(),
+        Value::Null => String::new(),
+        val => val.to_string(),
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Edit FILE in place
+fn edit(app: &ArgMatches) -> Result<(), String> {
+    let file = app.value_of("file").unwrap();
+    let ns = app.value_of("namespace").unwrap();
+    let data = std::fs::read(file).map_err(|err| format!("{}: {}", file, err))?;
+    let mut editor = NvsEditor::new(data).map_err(|err| format!("{}: {}", file, err))?;
+
+    if let Some(key) = app.value_of("set") {
+        let value = app.value_of("value").unwrap();
+        let value = parse_value(app.value_of("type").unwrap(), value)
+            .map_err(|err| format!("{}: {}", key, err))?;
+        editor
+            .set(ns, key, value)
+            .map_err(|err| format!("{}: {}", file, err))?;
+    } else if let Some(key) = app.value_of("erase") {
+        let erased = editor
+            .erase(ns, key)
+            .map_err(|err| format!("{}: {}", file, err))?;
+        if !erased {
+            return Err(format!("{}: key {} not found in {}", file, key, ns));
+        }
+    } else {
+        editor
+            .erase_namespace(ns)
+            .map_err(|err| format!("{}: {}", file, err))?;
+    }
+
+    std::fs::write(file, editor.data()).map_err(|err| format!("{}: {}", file, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAIRS: &[&str] = &["namespace", "key", "type", "value"];
+
+    fn records() -> Vec<Record> {
+        let mut generator = NvsGenerator::new(0x3000);
+        generator.add("app", "boots", EntryType::U8(7));
+        generator.add("app", "motd", EntryType::String("hi, \"you\"".to_owned()));
+        generator.add("app", "mac", EntryType::Blob(vec![0x24, 0x0a, 0xc4]));
+        let nvs = Nvs::parse(&generator.generate().unwrap()).unwrap();
+
+        let mut entries: Vec<&Entry> = nvs.namespace("app").unwrap().values().copied().collect();
+        entries.sort_by_key(|entry| (entry.page(), entry.start()));
+        entries
+            .iter()
+            .map(|entry| record(&nvs, entry, PAIRS))
+            .collect()
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(
+            render("text", &records()),
+            "app  boots  u8      7\n\
+             app  motd   string  hi, \"you\"\n\
+             app  mac    blob    240ac4\n"
+        );
+    }
+
+    #[test]
+    fn csv() {
+        assert_eq!(
+            render("csv", &records()),
+            "namespace,key,type,value\n\
+             app,boots,u8,7\n\
+             app,motd,string,\"hi, \"\"you\"\"\"\n\
+             app,mac,blob,240ac4\n"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let output: Value = serde_json::from_str(&render("json", &records())).unwrap();
+        assert_eq!(
+            output,
+            json!([
+                {"namespace": "app", "key": "boots", "type": "u8", "value": 7},
+                {"namespace": "app", "key": "motd", "type": "string", "value": "hi, \"you\""},
+                {"namespace": "app", "key": "mac", "type": "blob", "value": "240ac4"},
+            ])
+        );
+    }
+
+    #[test]
+    fn yaml() {
+        let records = records();
+        assert_eq!(
+            render("yaml", &records[..2]),
+            "- namespace: \"app\"\n  key: \"boots\"\n  type: \"u8\"\n  value: 7\n\
+             - namespace: \"app\"\n  key: \"motd\"\n  type: \"string\"\n  value: \"hi, \\\"you\\\"\"\n"
+        );
+        assert_eq!(render("yaml", &[]), "[]\n");
     }
 }
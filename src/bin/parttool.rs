@@ -1,6 +1,942 @@
-use esp32::partition_table::PartitionTable;
+use std::fs::File;
+use std::io::{Read, Write};
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use serde_json::json;
+
+use esp32::flash::{Chip, FlashImage, ImageBuilder};
+use esp32::partition_table::{
+    parse_int, IdfVersion, Md5Status, MemoryMap, NameRegistry, Partition, PartitionTable,
+    PartitionType, Severity, Subtype, DEFAULT_TABLE_OFFSET,
+};
+
+const VERSION: &str = "0.1.0";
 
 fn main() {
-    let partition_table = PartitionTable::from_file("partition_table.bin");
-    println!("{:?}", partition_table);
+    let offset = Arg::with_name("offset")
+        .long("offset")
+        .help("Offset the partition table is flashed at [default: 0x8000]")
+        .value_name("OFFSET")
+        .takes_value(true);
+    let file = Arg::with_name("file")
+        .value_name("FILE")
+        .help("Partition table as a binary, csv or full flash dump")
+        .takes_value(true)
+        .required(true);
+    let image = Arg::with_name("file")
+        .value_name("FILE")
+        .help("Full flash dump, such as the output of esptool read_flash")
+        .takes_value(true)
+        .required(true);
+    let name = Arg::with_name("name")
+        .short("n")
+        .long("name")
+        .help("Name of the partition")
+        .value_name("NAME")
+        .takes_value(true)
+        .required_unless("type")
+        .conflicts_with("type");
+    let partition_type = Arg::with_name("type")
+        .short("t")
+        .long("type")
+        .help("Type of the partition")
+        .value_name("TYPE")
+        .takes_value(true)
+        .requires("subtype");
+    let subtype = Arg::with_name("subtype")
+        .short("s")
+        .long("subtype")
+        .help("Subtype of the partition")
+        .value_name("SUBTYPE")
+        .takes_value(true)
+        .requires("type");
+
+    let app = App::new("parttool")
+        .version(VERSION)
+        .about("Host based tool for interacting with esp-idf partition tables")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(
+            Arg::with_name("names")
+                .long("names")
+                .help("File of custom partition type and subtype names")
+                .value_name("FILE")
+                .takes_value(true)
+                .global(true),
+        )
+        .subcommand(
+            SubCommand::with_name("show")
+                .about("Show all partitions in the table")
+                .arg(file.clone())
+                .arg(offset.clone())
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .help("Output format")
+                        .value_name("FORMAT")
+                        .possible_value("csv")
+                        .possible_value("json")
+                        .possible_value("text")
+                        .takes_value(true)
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("get")
+                .about("Get information about a single partition")
+                .arg(file.clone())
+                .arg(offset.clone())
+                .arg(name.clone())
+                .arg(partition_type.clone())
+                .arg(subtype.clone())
+                .arg(
+                    Arg::with_name("info")
+                        .short("i")
+                        .long("info")
+                        .help("Information to output")
+                        .value_name("INFO")
+                        .possible_value("name")
+                        .possible_value("type")
+                        .possible_value("subtype")
+                        .possible_value("offset")
+                        .possible_value("size")
+                        .possible_value("encrypted")
+                        .possible_value("readonly")
+                        .takes_value(true)
+                        .multiple(true)
+                        .default_value("offset"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .help("Output format")
+                        .value_name("FORMAT")
+                        .possible_value("json")
+                        .possible_value("text")
+                        .takes_value(true)
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("convert")
+                .about("Convert a partition table between csv and binary")
+                .arg(file.clone())
+                .arg(offset.clone())
+                .arg(
+                    Arg::with_name("out")
+                        .value_name("OUT")
+                        .help("Filename to write the converted table to")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("to")
+                        .long("to")
+                        .help("Format to convert to, defaults to the opposite of the input")
+                        .value_name("FORMAT")
+                        .possible_value("bin")
+                        .possible_value("csv")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("Check the partition table for problems")
+                .arg(file.clone())
+                .arg(offset.clone())
+                .arg(
+                    Arg::with_name("flash-size")
+                        .long("flash-size")
+                        .help("Size of the flash, e.g. 4MB")
+                        .value_name("SIZE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("idf-version")
+                        .long("idf-version")
+                        .help(
+                            "Warn about partitions not supported by this esp-idf release, e.g. 5.1",
+                        )
+                        .value_name("VERSION")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .help("Output format")
+                        .value_name("FORMAT")
+                        .possible_value("json")
+                        .possible_value("text")
+                        .takes_value(true)
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compare two partition tables and check they can be migrated over OTA")
+                .arg(
+                    Arg::with_name("old")
+                        .value_name("OLD")
+                        .help("Partition table the device is using")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("new")
+                        .value_name("NEW")
+                        .help("Partition table the device is being updated to")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(offset.clone())
+                .arg(
+                    Arg::with_name("firmware")
+                        .long("firmware")
+                        .help("App binary that will be written to the app slots")
+                        .value_name("FIRMWARE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .help("Output format")
+                        .value_name("FORMAT")
+                        .possible_value("json")
+                        .possible_value("text")
+                        .takes_value(true)
+                        .default_value("text"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("grow")
+                .about("Re-plan a partition table for a larger flash")
+                .arg(file.clone())
+                .arg(offset.clone())
+                .arg(
+                    Arg::with_name("flash-size")
+                        .long("flash-size")
+                        .help("Size of the new flash, e.g. 8MB")
+                        .value_name("SIZE")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("pin")
+                        .long("pin")
+                        .help("Name of a partition that must not move")
+                        .value_name("NAME")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("fill")
+                        .long("fill")
+                        .help("Name of the partition to grow into the remaining space")
+                        .value_name("NAME")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .value_name("OUT")
+                        .help("Filename to write the new table to, as csv or binary by extension")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("map")
+                .about("Draw a map of the flash layout")
+                .arg(file.clone())
+                .arg(offset.clone())
+                .arg(
+                    Arg::with_name("flash-size")
+                        .long("flash-size")
+                        .help("Size of the flash, e.g. 4MB")
+                        .value_name("SIZE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("partition")
+                        .short("p")
+                        .long("partition")
+                        .help("Payload of a partition as NAME=FILE to show its usage")
+                        .value_name("NAME=FILE")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("svg")
+                        .long("svg")
+                        .help("Also write the map as an SVG to this file")
+                        .value_name("SVG")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("merge_bin")
+                .about("Build a single flashable image from a bootloader, table and partitions")
+                .arg(file)
+                .arg(offset.clone())
+                .arg(
+                    Arg::with_name("chip")
+                        .long("chip")
+                        .help("Chip the image is for, this determines the bootloader offset")
+                        .value_name("CHIP")
+                        .possible_values(&[
+                            "esp32", "esp32s2", "esp32s3", "esp32c3", "esp32c6", "esp32h2",
+                        ])
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("bootloader")
+                        .long("bootloader")
+                        .help("Second stage bootloader binary")
+                        .value_name("BOOTLOADER")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("partition")
+                        .short("p")
+                        .long("partition")
+                        .help("Contents of a partition as NAME=FILE")
+                        .value_name("NAME=FILE")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1),
+                )
+                .arg(
+                    Arg::with_name("flash-size")
+                        .long("flash-size")
+                        .help("Pad the image to the size of the flash, e.g. 4MB")
+                        .value_name("SIZE")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("out")
+                        .long("output")
+                        .help("Filename to write the image to")
+                        .value_name("OUT")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("scan")
+                .about("Search a binary for partition tables, most likely first")
+                .arg(image.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("read_partition")
+                .about("Read the contents of a partition out of a flash dump")
+                .arg(image.clone())
+                .arg(offset.clone())
+                .arg(name.clone())
+                .arg(partition_type.clone())
+                .arg(subtype.clone())
+                .arg(
+                    Arg::with_name("out")
+                        .long("output")
+                        .help("Filename to write the partition contents to")
+                        .value_name("OUT")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("write_partition")
+                .about("Replace the contents of a partition in a flash dump")
+                .arg(image.clone())
+                .arg(offset.clone())
+                .arg(name.clone())
+                .arg(partition_type.clone())
+                .arg(subtype.clone())
+                .arg(
+                    Arg::with_name("input")
+                        .long("input")
+                        .help("Filename of the new partition contents")
+                        .value_name("INPUT")
+                        .takes_value(true)
+                        .required(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("erase_partition")
+                .about("Erase a partition in a flash dump")
+                .arg(image)
+                .arg(offset)
+                .arg(name)
+                .arg(partition_type)
+                .arg(subtype),
+        )
+        .get_matches();
+
+    let (command, args) = app.subcommand();
+    let args = args.unwrap();
+    let result = load_names(args).and_then(|names| match command {
+        "show" => show(args, &names),
+        "get" => get(args, &names),
+        "convert" => convert(args, &names),
+        "validate" => validate(args, &names),
+        "diff" => diff(args, &names),
+        "grow" => grow(args, &names),
+        "map" => map(args, &names),
+        "merge_bin" => merge_bin(args, &names),
+        "scan" => scan(args),
+        "read_partition" => read_partition(args, &names),
+        "write_partition" => write_partition(args, &names),
+        "erase_partition" => erase_partition(args, &names),
+        _ => unreachable!(),
+    });
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    }
+}
+
+#[derive(PartialEq)]
+enum Format {
+    Binary,
+    Csv,
+}
+
+/// Load a partition table from a binary table, a csv or a full flash dump. The
+/// offset is where the table is flashed, which is used to find it in a flash dump
+/// and to place partitions without an offset in a csv.
+fn load(args: &ArgMatches, names: &NameRegistry) -> Result<(PartitionTable, Format), String> {
+    load_file(args.value_of("file").unwrap(), table_offset(args)?, names)
+}
+
+fn load_file(
+    file: &str,
+    offset: u32,
+    names: &NameRegistry,
+) -> Result<(PartitionTable, Format), String> {
+    let data = read_file(file)?;
+
+    if data.starts_with(&[0xaa, 0x50]) {
+        let table = PartitionTable::parse(&data).map_err(|err| format!("{}: {}", file, err))?;
+        return Ok((table, Format::Binary));
+    }
+
+    if let Ok(csv) = std::str::from_utf8(&data) {
+        let table = PartitionTable::from_csv_with_names(csv, offset, names)
+            .map_err(|err| format!("{}: {}", file, err))?;
+        return Ok((table, Format::Csv));
+    }
+
+    let offset = offset as usize;
+    if offset >= data.len() {
+        return Err(format!(
+            "{}: offset {:#x} is past the end of the file",
+            file, offset
+        ));
+    }
+
+    let table =
+        PartitionTable::parse(&data[offset..]).map_err(|err| format!("{}: {}", file, err))?;
+    Ok((table, Format::Binary))
+}
+
+/// Load the custom type and subtype names given with --names
+fn load_names(args: &ArgMatches) -> Result<NameRegistry, String> {
+    let file = match args.value_of("names") {
+        Some(file) => file,
+        None => return Ok(NameRegistry::new()),
+    };
+
+    let data = read_file(file)?;
+    let input = std::str::from_utf8(&data).map_err(|err| format!("{}: {}", file, err))?;
+    NameRegistry::parse(input).map_err(|err| format!("{}: {}", file, err))
+}
+
+fn table_offset(args: &ArgMatches) -> Result<u32, String> {
+    match args.value_of("offset") {
+        Some(offset) => parse_int(offset).ok_or_else(|| format!("invalid offset '{}'", offset)),
+        None => Ok(DEFAULT_TABLE_OFFSET),
+    }
+}
+
+fn flash_size(args: &ArgMatches) -> Result<Option<u32>, String> {
+    match args.value_of("flash-size") {
+        Some(size) => parse_int(size.trim_end_matches(['B', 'b']))
+            .map(Some)
+            .ok_or_else(|| format!("invalid flash size '{}'", size)),
+        None => Ok(None),
+    }
+}
+
+fn read_file(file: &str) -> Result<Vec<u8>, String> {
+    let mut data = vec![];
+    File::open(file)
+        .and_then(|mut f| f.read_to_end(&mut data))
+        .map_err(|err| format!("{}: {}", file, err))?;
+    Ok(data)
+}
+
+fn show(args: &ArgMatches, names: &NameRegistry) -> Result<(), String> {
+    let (table, _) = load(args, names)?;
+
+    match args.value_of("output").unwrap() {
+        "csv" => print!("{}", table.to_csv_with_names(names)),
+        "json" => {
+            let partitions: Vec<_> = table
+                .partitions()
+                .iter()
+                .map(|partition| partition_json(partition, names))
+                .collect();
+            let output = json!({
+                "md5": table.md5().map(|md5| hex(md5)),
+                "md5_matches": table.md5_matches(),
+                "partitions": partitions,
+            });
+            println!("{}", serde_json::to_string_pretty(&output).unwrap());
+        }
+        _ => {
+            println!(
+                "{:<16}  {:<15}  {:<12}  {:>10}  {:>10}  {:>6}  Flags",
+                "Name", "Type", "SubType", "Offset", "Size", ""
+            );
+            for partition in table.partitions() {
+                println!(
+                    "{:<16}  {:<15}  {:<12}  {:>10}  {:>10}  {:>6}  {}",
+                    partition.name(),
+                    type_name(names, partition.partition_type()),
+                    subtype_name(names, partition.partition_type(), partition.subtype()),
+                    format!("{:#x}", partition.offset()),
+                    format!("{:#x}", partition.size()),
+                    human_size(partition.size()),
+                    flag_names(partition).join(":"),
+                );
+            }
+
+            match table.md5_matches() {
+                Some(true) => println!("\nMD5 {} (valid)", hex(table.md5().unwrap())),
+                Some(false) => println!("\nMD5 {} (invalid)", hex(table.md5().unwrap())),
+                None => println!("\nMD5 disabled"),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn get(args: &ArgMatches, names: &NameRegistry) -> Result<(), String> {
+    let (table, _) = load(args, names)?;
+
+    let partition = select(&table, args, names)?;
+
+    let info: Vec<&str> = args.values_of("info").unwrap().collect();
+    if args.value_of("output") == Some("json") {
+        let output = partition_json(partition, names);
+        let output: serde_json::Map<String, serde_json::Value> = info
+            .iter()
+            .map(|info| {
+                let value = match *info {
+                    "encrypted" => json!(partition.is_encrypted()),
+                    "readonly" => json!(partition.is_readonly()),
+                    _ => output[*info].clone(),
+                };
+                (info.to_string(), value)
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+    } else {
+        let values: Vec<String> = info
+            .iter()
+            .map(|info| match *info {
+                "name" => partition.name().to_owned(),
+                "type" => type_name(names, partition.partition_type()),
+                "subtype" => subtype_name(names, partition.partition_type(), partition.subtype()),
+                "size" => format!("{:#x}", partition.size()),
+                "encrypted" => partition.is_encrypted().to_string(),
+                "readonly" => partition.is_readonly().to_string(),
+                _ => format!("{:#x}", partition.offset()),
+            })
+            .collect();
+        println!("{}", values.join(" "));
+    }
+
+    Ok(())
+}
+
+/// Find the partition selected by either --name or --type and --subtype, the
+/// first match is used when several partitions have the same type and subtype
+fn select<'a>(
+    table: &'a PartitionTable,
+    args: &ArgMatches,
+    names: &NameRegistry,
+) -> Result<&'a Partition, String> {
+    match args.value_of("name") {
+        Some(name) => table
+            .find_by_name(name)
+            .ok_or_else(|| format!("no partition named {}", name)),
+        None => {
+            let type_value = args.value_of("type").unwrap();
+            let subtype_value = args.value_of("subtype").unwrap();
+            let partition_type = names
+                .find_type(type_value)
+                .or_else(|| {
+                    parse_int(type_value)
+                        .filter(|value| *value <= 0xff)
+                        .map(|value| PartitionType::from(value as u8))
+                })
+                .ok_or_else(|| format!("invalid type '{}'", type_value))?;
+            let subtype = names
+                .find_subtype(&partition_type, subtype_value)
+                .or_else(|| {
+                    parse_int(subtype_value)
+                        .filter(|value| *value <= 0xff)
+                        .map(|value| Subtype::new(&partition_type, value as u8))
+                })
+                .ok_or_else(|| format!("invalid subtype '{}'", subtype_value))?;
+
+            table
+                .find(&partition_type, &subtype, None)
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    format!(
+                        "no partition with type {} and subtype {}",
+                        type_value, subtype_value
+                    )
+                })
+        }
+    }
+}
+
+fn convert(args: &ArgMatches, names: &NameRegistry) -> Result<(), String> {
+    let (table, format) = load(args, names)?;
+    let out = args.value_of("out").unwrap();
+
+    let to = match args.value_of("to") {
+        Some("bin") => Format::Binary,
+        Some(_) => Format::Csv,
+        None if format == Format::Binary => Format::Csv,
+        None => Format::Binary,
+    };
+
+    let data = match to {
+        Format::Binary => table.to_bytes(),
+        Format::Csv => table.to_csv_with_names(names).into_bytes(),
+    };
+
+    File::create(out)
+        .and_then(|mut f| f.write_all(&data))
+        .map_err(|err| format!("{}: {}", out, err))
+}
+
+fn validate(args: &ArgMatches, names: &NameRegistry) -> Result<(), String> {
+    let (table, _) = load(args, names)?;
+
+    let flash_size = flash_size(args)?;
+    let table_offset = table_offset(args)?;
+
+    let idf_version = match args.value_of("idf-version") {
+        Some(version) => Some(
+            IdfVersion::parse(version)
+                .ok_or_else(|| format!("invalid esp-idf version '{}'", version))?,
+        ),
+        None => None,
+    };
+    // types and subtypes from --names are unknown to esp-idf but are expected
+    let diagnostics = table.validate_with_names(table_offset, flash_size, idf_version, names);
+
+    if args.value_of("output") == Some("json") {
+        let output: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| {
+                json!({
+                    "severity": diagnostic.severity().to_string(),
+                    "message": diagnostic.kind().to_string(),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+    } else {
+        diagnostics
+            .iter()
+            .for_each(|diagnostic| println!("{}", diagnostic));
+    }
+
+    let errors = diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.severity() == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(format!("found {} error(s)", errors));
+    }
+
+    Ok(())
+}
+
+fn diff(args: &ArgMatches, names: &NameRegistry) -> Result<(), String> {
+    let offset = table_offset(args)?;
+    let (old, _) = load_file(args.value_of("old").unwrap(), offset, names)?;
+    let (new, _) = load_file(args.value_of("new").unwrap(), offset, names)?;
+    let firmware_size = match args.value_of("firmware") {
+        Some(firmware) => Some(read_file(firmware)?.len() as u32),
+        None => None,
+    };
+
+    let changes = old.diff(&new);
+    let issues = old.check_migration(&new, firmware_size);
+    if args.value_of("output") == Some("json") {
+        let changes: Vec<_> = changes.iter().map(|change| change.to_string()).collect();
+        let issues: Vec<_> = issues
+            .iter()
+            .map(|issue| {
+                json!({
+                    "severity": issue.severity().to_string(),
+                    "message": issue.kind().to_string(),
+                })
+            })
+            .collect();
+        let output = json!({
+            "changes": changes,
+            "migration": issues,
+        });
+        println!("{}", serde_json::to_string_pretty(&output).unwrap());
+    } else {
+        changes.iter().for_each(|change| println!("{}", change));
+        if !changes.is_empty() && !issues.is_empty() {
+            println!();
+        }
+        issues.iter().for_each(|issue| println!("{}", issue));
+    }
+
+    let errors = issues
+        .iter()
+        .filter(|issue| issue.severity() == Severity::Error)
+        .count();
+    if errors > 0 {
+        return Err(format!(
+            "found {} error(s) migrating to the new table over OTA",
+            errors
+        ));
+    }
+
+    Ok(())
+}
+
+fn grow(args: &ArgMatches, names: &NameRegistry) -> Result<(), String> {
+    let (table, _) = load(args, names)?;
+    let flash_size = flash_size(args)?.unwrap();
+    let pinned: Vec<&str> = args.values_of("pin").into_iter().flatten().collect();
+    for name in &pinned {
+        if table.find_by_name(name).is_none() {
+            return Err(format!("no partition named {}", name));
+        }
+    }
+
+    let table = table
+        .grow_with_offset(
+            table_offset(args)?,
+            flash_size,
+            &pinned,
+            args.value_of("fill"),
+        )
+        .map_err(|err| err.to_string())?;
+
+    let out = args.value_of("out").unwrap();
+    let data = if out.ends_with(".csv") {
+        table.to_csv_with_names(names).into_bytes()
+    } else {
+        table.to_bytes()
+    };
+    File::create(out)
+        .and_then(|mut f| f.write_all(&data))
+        .map_err(|err| format!("{}: {}", out, err))
+}
+
+fn map(args: &ArgMatches, names: &NameRegistry) -> Result<(), String> {
+    let (table, _) = load(args, names)?;
+
+    let mut map = MemoryMap::new(&table, table_offset(args)?, flash_size(args)?).names(names);
+    for partition in args.values_of("partition").into_iter().flatten() {
+        let (name, file) = partition
+            .split_once('=')
+            .ok_or_else(|| format!("invalid partition '{}', expected NAME=FILE", partition))?;
+        if table.find_by_name(name).is_none() {
+            return Err(format!("no partition named {}", name));
+        }
+        map = map.usage(name, read_file(file)?.len() as u32);
+    }
+
+    print!("{}", map.to_text());
+
+    if let Some(svg) = args.value_of("svg") {
+        File::create(svg)
+            .and_then(|mut f| f.write_all(map.to_svg().as_bytes()))
+            .map_err(|err| format!("{}: {}", svg, err))?;
+    }
+
+    Ok(())
+}
+
+fn merge_bin(args: &ArgMatches, names: &NameRegistry) -> Result<(), String> {
+    let (table, _) = load(args, names)?;
+    let chip = Chip::from_name(args.value_of("chip").unwrap()).unwrap();
+
+    let mut builder = ImageBuilder::new(chip, table).table_offset(table_offset(args)?);
+    if let Some(bootloader) = args.value_of("bootloader") {
+        builder = builder.bootloader(read_file(bootloader)?);
+    }
+    for partition in args.values_of("partition").into_iter().flatten() {
+        let (name, file) = partition
+            .split_once('=')
+            .ok_or_else(|| format!("invalid partition '{}', expected NAME=FILE", partition))?;
+        builder = builder.payload(name, read_file(file)?);
+    }
+    if let Some(flash_size) = flash_size(args)? {
+        builder = builder.flash_size(flash_size);
+    }
+
+    let out = args.value_of("out").unwrap();
+    builder
+        .build()
+        .map_err(|err| err.to_string())?
+        .save(out)
+        .map_err(|err| format!("{}: {}", out, err))
+}
+
+fn scan(args: &ArgMatches) -> Result<(), String> {
+    let file = args.value_of("file").unwrap();
+    let data = read_file(file)?;
+
+    let candidates = PartitionTable::scan(&data);
+    if candidates.is_empty() {
+        return Err(format!("{}: no partition tables found", file));
+    }
+
+    println!(
+        "{:>10}  {:>10}  {:<8}  Errors",
+        "Offset", "Partitions", "MD5"
+    );
+    for candidate in candidates {
+        let md5 = match candidate.md5_status() {
+            Md5Status::Valid => "valid",
+            Md5Status::Missing => "disabled",
+            Md5Status::Mismatch => "invalid",
+        };
+        println!(
+            "{:>10}  {:>10}  {:<8}  {}",
+            format!("{:#x}", candidate.offset()),
+            candidate.table().partitions().len(),
+            md5,
+            candidate.errors()
+        );
+    }
+
+    Ok(())
+}
+
+/// Load a full flash dump, using --offset to locate the partition table when it
+/// is given instead of searching the image for it
+fn load_image(args: &ArgMatches) -> Result<FlashImage, String> {
+    let file = args.value_of("file").unwrap();
+    let data = read_file(file)?;
+
+    let image = match args.value_of("offset") {
+        Some(_) => FlashImage::with_table_offset(data, table_offset(args)?),
+        None => FlashImage::new(data),
+    };
+    image.map_err(|err| format!("{}: {}", file, err))
+}
+
+fn save_image(image: &FlashImage, args: &ArgMatches) -> Result<(), String> {
+    let file = args.value_of("file").unwrap();
+    image.save(file).map_err(|err| format!("{}: {}", file, err))
+}
+
+fn read_partition(args: &ArgMatches, names: &NameRegistry) -> Result<(), String> {
+    let image = load_image(args)?;
+    let partition = select(image.table(), args, names)?;
+    let out = args.value_of("out").unwrap();
+
+    let data = image
+        .read_partition(partition)
+        .map_err(|err| err.to_string())?;
+    File::create(out)
+        .and_then(|mut f| f.write_all(data))
+        .map_err(|err| format!("{}: {}", out, err))
+}
+
+fn write_partition(args: &ArgMatches, names: &NameRegistry) -> Result<(), String> {
+    let mut image = load_image(args)?;
+    let partition = select(image.table(), args, names)?.clone();
+    let input = args.value_of("input").unwrap();
+
+    let data = read_file(input)?;
+
+    image
+        .write_partition(&partition, &data)
+        .map_err(|err| err.to_string())?;
+    save_image(&image, args)
+}
+
+fn erase_partition(args: &ArgMatches, names: &NameRegistry) -> Result<(), String> {
+    let mut image = load_image(args)?;
+    let partition = select(image.table(), args, names)?.clone();
+
+    image
+        .erase_partition(&partition)
+        .map_err(|err| err.to_string())?;
+    save_image(&image, args)
+}
+
+fn partition_json(partition: &Partition, names: &NameRegistry) -> serde_json::Value {
+    json!({
+        "name": partition.name(),
+        "type": type_name(names, partition.partition_type()),
+        "subtype": subtype_name(names, partition.partition_type(), partition.subtype()),
+        "offset": partition.offset(),
+        "size": partition.size(),
+        "flags": flag_names(partition),
+    })
+}
+
+fn type_name(names: &NameRegistry, partition_type: &PartitionType) -> String {
+    match names.type_name(partition_type) {
+        Some(name) => name.to_owned(),
+        None => format!("{:#x}", partition_type.value()),
+    }
+}
+
+fn subtype_name(names: &NameRegistry, partition_type: &PartitionType, subtype: &Subtype) -> String {
+    match names.subtype_name(partition_type, subtype) {
+        Some(name) => name.to_owned(),
+        None => format!("{:#x}", subtype.value()),
+    }
+}
+
+fn flag_names(partition: &Partition) -> Vec<&'static str> {
+    let mut flags = vec![];
+    if partition.is_encrypted() {
+        flags.push("encrypted");
+    }
+    if partition.is_readonly() {
+        flags.push("readonly");
+    }
+    flags
+}
+
+fn human_size(size: u32) -> String {
+    if size >= 1024 * 1024 && size % (1024 * 1024) == 0 {
+        format!("{}M", size / (1024 * 1024))
+    } else if size >= 1024 && size % 1024 == 0 {
+        format!("{}K", size / 1024)
+    } else {
+        format!("{}", size)
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
 }
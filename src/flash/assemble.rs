@@ -0,0 +1,253 @@
+use std::ops::Range;
+
+use crate::flash::{FlashError, FlashImage};
+use crate::partition_table::{PartitionTable, DEFAULT_TABLE_OFFSET, TABLE_REGION_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    Esp32,
+    Esp32S2,
+    Esp32S3,
+    Esp32C3,
+    Esp32C6,
+    Esp32H2,
+}
+
+impl Chip {
+    /// Where the ROM loads the second stage bootloader from
+    pub fn bootloader_offset(&self) -> u32 {
+        match self {
+            Self::Esp32 | Self::Esp32S2 => 0x1000,
+            Self::Esp32S3 | Self::Esp32C3 | Self::Esp32C6 | Self::Esp32H2 => 0x0,
+        }
+    }
+
+    /// The name esptool uses for the chip
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Esp32 => "esp32",
+            Self::Esp32S2 => "esp32s2",
+            Self::Esp32S3 => "esp32s3",
+            Self::Esp32C3 => "esp32c3",
+            Self::Esp32C6 => "esp32c6",
+            Self::Esp32H2 => "esp32h2",
+        }
+    }
+
+    /// Look up a chip by the name esptool uses for it
+    pub fn from_name(name: &str) -> Option<Chip> {
+        match name {
+            "esp32" => Some(Self::Esp32),
+            "esp32s2" => Some(Self::Esp32S2),
+            "esp32s3" => Some(Self::Esp32S3),
+            "esp32c3" => Some(Self::Esp32C3),
+            "esp32c6" => Some(Self::Esp32C6),
+            "esp32h2" => Some(Self::Esp32H2),
+            _ => None,
+        }
+    }
+}
+
+/// Builds a single image that can be flashed at 0x0 from a bootloader, a partition
+/// table and the contents of its partitions, the same as `esptool merge_bin`. Any
+/// space not covered by the bootloader, table or a payload is filled with 0xFF.
+#[derive(Debug, Clone)]
+pub struct ImageBuilder {
+    chip: Chip,
+    table: PartitionTable,
+    table_offset: u32,
+    bootloader: Option<Vec<u8>>,
+    payloads: Vec<(String, Vec<u8>)>,
+    flash_size: Option<u32>,
+}
+
+impl ImageBuilder {
+    /// Start an image for `chip` with the table at the default offset of 0x8000
+    pub fn new(chip: Chip, table: PartitionTable) -> ImageBuilder {
+        ImageBuilder {
+            chip,
+            table,
+            table_offset: DEFAULT_TABLE_OFFSET,
+            bootloader: None,
+            payloads: vec![],
+            flash_size: None,
+        }
+    }
+
+    /// Place the partition table at `table_offset` instead of 0x8000
+    pub fn table_offset(mut self, table_offset: u32) -> ImageBuilder {
+        self.table_offset = table_offset;
+        self
+    }
+
+    pub fn bootloader(mut self, bootloader: Vec<u8>) -> ImageBuilder {
+        self.bootloader = Some(bootloader);
+        self
+    }
+
+    /// Contents of the partition named `name`, such as an app binary or an NVS
+    /// image. The contents are placed at the offset the table gives the partition.
+    pub fn payload(mut self, name: &str, contents: Vec<u8>) -> ImageBuilder {
+        self.payloads.push((name.to_owned(), contents));
+        self
+    }
+
+    /// Pad the image out to the full size of the flash. Without this the image
+    /// ends directly after the last thing written to it.
+    pub fn flash_size(mut self, flash_size: u32) -> ImageBuilder {
+        self.flash_size = Some(flash_size);
+        self
+    }
+
+    pub fn build(&self) -> Result<FlashImage, FlashError> {
+        let table = self.table.to_bytes();
+        let table_offset = self.table_offset as usize;
+        let mut len = table_offset + table.len();
+
+        if let Some(bootloader) = &self.bootloader {
+            let max = self
+                .table_offset
+                .saturating_sub(self.chip.bootloader_offset());
+            if bootloader.len() > max as usize {
+                return Err(FlashError::BootloaderTooLarge {
+                    size: bootloader.len(),
+                    max,
+                });
+            }
+        }
+
+        // nothing but the bootloader is written before the table, including the
+        // space below the bootloader offset on chips that don't load it from 0
+        let bootloader_region = 0..table_offset;
+        let table_region = table_offset..table_offset + TABLE_REGION_SIZE as usize;
+        let overlaps = |a: &Range<usize>, b: &Range<usize>| a.start < b.end && b.start < a.end;
+
+        let mut payloads: Vec<(&str, Range<usize>, &Vec<u8>)> =
+            Vec::with_capacity(self.payloads.len());
+        for (name, contents) in &self.payloads {
+            if payloads.iter().any(|(other, _, _)| other == name) {
+                return Err(FlashError::DuplicatePayload { name: name.clone() });
+            }
+
+            let partition = self
+                .table
+                .find_by_name(name)
+                .ok_or_else(|| FlashError::UnknownPartition { name: name.clone() })?;
+            if contents.len() > partition.size() as usize {
+                return Err(FlashError::TooLarge {
+                    name: name.clone(),
+                    size: contents.len(),
+                    max: partition.size(),
+                });
+            }
+
+            let offset = partition.offset() as usize;
+            let range = offset..offset + contents.len();
+            if overlaps(&range, &bootloader_region) {
+                return Err(FlashError::OverlapsBootloader { name: name.clone() });
+            }
+            if overlaps(&range, &table_region) {
+                return Err(FlashError::OverlapsTable { name: name.clone() });
+            }
+            // partitions sharing an offset are rejected even when one is empty
+            if let Some((other, _, _)) = payloads
+                .iter()
+                .find(|(_, other, _)| other.start == range.start || overlaps(other, &range))
+            {
+                return Err(FlashError::PayloadOverlap {
+                    name: name.clone(),
+                    other: (*other).to_owned(),
+                });
+            }
+
+            len = len.max(range.end);
+            payloads.push((name, range, contents));
+        }
+
+        if let Some(flash_size) = self.flash_size {
+            if len > flash_size as usize {
+                return Err(FlashError::ImageTooLarge {
+                    size: len,
+                    flash_size,
+                });
+            }
+            len = flash_size as usize;
+        }
+
+        let mut data = vec![0xff; len];
+        if let Some(bootloader) = &self.bootloader {
+            let offset = self.chip.bootloader_offset() as usize;
+            data[offset..offset + bootloader.len()].copy_from_slice(bootloader);
+        }
+        data[table_offset..table_offset + table.len()].copy_from_slice(&table);
+        for (_, range, contents) in payloads {
+            data[range].copy_from_slice(contents);
+        }
+
+        Ok(FlashImage {
+            data,
+            table_offset: self.table_offset,
+            table: self.table.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> ImageBuilder {
+        let table = PartitionTable::from_csv(
+            "low,data,0x40,0x0,4K\n\
+             boot,data,0x40,0x2000,4K\n\
+             table,data,0x40,0x8000,4K\n\
+             nvs,data,nvs,0x9000,24K\n\
+             copy,data,0x40,0x9000,4K\n\
+             factory,app,factory,0x10000,1M\n",
+        )
+        .unwrap();
+        ImageBuilder::new(Chip::Esp32, table)
+    }
+
+    #[test]
+    fn payloads_are_placed_at_their_partition() {
+        let image = builder()
+            .payload("nvs", vec![1; 16])
+            .payload("factory", vec![2; 16])
+            .build()
+            .unwrap();
+        assert_eq!(image.data[0x9000..0x9010], [1; 16]);
+        assert_eq!(image.data[0x10000..0x10010], [2; 16]);
+        assert_eq!(image.data.len(), 0x10010);
+    }
+
+    #[test]
+    fn overlapping_payloads_are_rejected() {
+        let build = |builder: ImageBuilder| builder.build().unwrap_err();
+
+        assert!(matches!(
+            build(builder().payload("boot", vec![0; 16])),
+            FlashError::OverlapsBootloader { .. }
+        ));
+        assert!(matches!(
+            build(builder().payload("low", vec![0; 16])),
+            FlashError::OverlapsBootloader { .. }
+        ));
+        assert!(matches!(
+            build(builder().payload("table", vec![0; 16])),
+            FlashError::OverlapsTable { .. }
+        ));
+        assert!(matches!(
+            build(builder().payload("nvs", vec![]).payload("nvs", vec![])),
+            FlashError::DuplicatePayload { .. }
+        ));
+        assert!(matches!(
+            build(
+                builder()
+                    .payload("nvs", vec![0; 16])
+                    .payload("copy", vec![])
+            ),
+            FlashError::PayloadOverlap { .. }
+        ));
+    }
+}
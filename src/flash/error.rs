@@ -0,0 +1,130 @@
+use std::fmt::Formatter;
+
+use crate::partition_table::PartitionTableError;
+
+#[derive(Debug)]
+pub enum FlashError {
+    Io(std::io::Error),
+    /// The partition table at the given offset could not be parsed
+    Table {
+        offset: u32,
+        source: PartitionTableError,
+    },
+    /// No partition table could be found in the image
+    TableNotFound,
+    /// The partition extends past the end of the image
+    OutOfBounds {
+        name: String,
+    },
+    /// The contents are larger than the partition they are being written to
+    TooLarge {
+        name: String,
+        size: usize,
+        max: u32,
+    },
+    /// The partition does not start on a flash sector boundary
+    Misaligned {
+        name: String,
+        offset: u32,
+    },
+    /// The partition has the readonly flag set
+    ReadOnly {
+        name: String,
+    },
+    /// The table does not have a partition with the given name
+    UnknownPartition {
+        name: String,
+    },
+    /// The bootloader runs into the partition table
+    BootloaderTooLarge {
+        size: usize,
+        max: u32,
+    },
+    /// The assembled image is larger than the flash
+    ImageTooLarge {
+        size: usize,
+        flash_size: u32,
+    },
+    /// The contents of the partition would be written over the bootloader region
+    OverlapsBootloader {
+        name: String,
+    },
+    /// The contents of the partition would be written over the partition table
+    OverlapsTable {
+        name: String,
+    },
+    /// Contents were given more than once for the same partition
+    DuplicatePayload {
+        name: String,
+    },
+    /// The contents of two partitions would be written to the same place
+    PayloadOverlap {
+        name: String,
+        other: String,
+    },
+}
+
+impl std::fmt::Display for FlashError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "unable to access flash image: {}", err),
+            Self::Table { offset, source } => {
+                write!(f, "partition table at {:#x}: {}", offset, source)
+            }
+            Self::TableNotFound => write!(f, "unable to find a partition table"),
+            Self::OutOfBounds { name } => {
+                write!(f, "partition {} extends past the end of the image", name)
+            }
+            Self::TooLarge { name, size, max } => write!(
+                f,
+                "{} bytes does not fit in partition {} of {} bytes",
+                size, name, max
+            ),
+            Self::Misaligned { name, offset } => write!(
+                f,
+                "partition {} at {:#x} is not aligned to a flash sector",
+                name, offset
+            ),
+            Self::ReadOnly { name } => write!(f, "partition {} is readonly", name),
+            Self::UnknownPartition { name } => write!(f, "no partition named {}", name),
+            Self::BootloaderTooLarge { size, max } => write!(
+                f,
+                "bootloader of {} bytes overlaps the partition table, the maximum is {} bytes",
+                size, max
+            ),
+            Self::ImageTooLarge { size, flash_size } => write!(
+                f,
+                "image of {} bytes does not fit in {} bytes of flash",
+                size, flash_size
+            ),
+            Self::OverlapsBootloader { name } => {
+                write!(f, "partition {} overlaps the bootloader", name)
+            }
+            Self::OverlapsTable { name } => {
+                write!(f, "partition {} overlaps the partition table", name)
+            }
+            Self::DuplicatePayload { name } => {
+                write!(f, "contents for partition {} given more than once", name)
+            }
+            Self::PayloadOverlap { name, other } => {
+                write!(f, "partitions {} and {} overlap", name, other)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FlashError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::Table { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for FlashError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
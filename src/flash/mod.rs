@@ -0,0 +1,260 @@
+mod assemble;
+mod error;
+
+use std::fs::File;
+use std::io::{Read, Write};
+
+use crate::partition_table::{Partition, PartitionTable, DATA_ALIGNMENT};
+
+pub use assemble::{Chip, ImageBuilder};
+pub use error::FlashError;
+
+/// A full image of the flash, such as the output of `esptool read_flash`, along
+/// with the partition table found in it. This allows partitions to be read and
+/// written offline the same way parttool.py does against a device.
+#[derive(Debug, Clone)]
+pub struct FlashImage {
+    data: Vec<u8>,
+    table_offset: u32,
+    table: PartitionTable,
+}
+
+impl FlashImage {
+    /// Load a flash image, searching it for the partition table. The most likely
+    /// table is used when there are several, see `PartitionTable::scan`.
+    pub fn new(data: Vec<u8>) -> Result<FlashImage, FlashError> {
+        let candidate = PartitionTable::scan(&data)
+            .into_iter()
+            .next()
+            .ok_or(FlashError::TableNotFound)?;
+
+        Ok(FlashImage {
+            table_offset: candidate.offset(),
+            table: candidate.into_table(),
+            data,
+        })
+    }
+
+    /// Load a flash image with the partition table at `table_offset`
+    pub fn with_table_offset(data: Vec<u8>, table_offset: u32) -> Result<FlashImage, FlashError> {
+        let table = data
+            .get(table_offset as usize..)
+            .ok_or(FlashError::TableNotFound)
+            .and_then(|table| {
+                PartitionTable::parse(table).map_err(|source| FlashError::Table {
+                    offset: table_offset,
+                    source,
+                })
+            })?;
+
+        Ok(FlashImage {
+            data,
+            table_offset,
+            table,
+        })
+    }
+
+    pub fn from_file(filename: &str) -> Result<FlashImage, FlashError> {
+        let mut file = File::open(filename)?;
+        let mut data = vec![];
+        file.read_to_end(&mut data)?;
+
+        FlashImage::new(data)
+    }
+
+    /// Write the full image back out to a file
+    pub fn save(&self, filename: &str) -> Result<(), FlashError> {
+        let mut file = File::create(filename)?;
+        file.write_all(&self.data)?;
+        Ok(())
+    }
+
+    pub fn table(&self) -> &PartitionTable {
+        &self.table
+    }
+
+    pub fn table_offset(&self) -> u32 {
+        self.table_offset
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// The full contents of a partition
+    pub fn read_partition(&self, partition: &Partition) -> Result<&[u8], FlashError> {
+        let (start, end) = self.bounds(partition)?;
+        Ok(&self.data[start..end])
+    }
+
+    /// Replace the contents of a partition. The partition is erased first so any
+    /// space not covered by `contents` is left as 0xFF.
+    pub fn write_partition(
+        &mut self,
+        partition: &Partition,
+        contents: &[u8],
+    ) -> Result<(), FlashError> {
+        if contents.len() > partition.size() as usize {
+            return Err(FlashError::TooLarge {
+                name: partition.name().to_owned(),
+                size: contents.len(),
+                max: partition.size(),
+            });
+        }
+
+        self.erase_partition(partition)?;
+        let start = partition.offset() as usize;
+        self.data[start..start + contents.len()].copy_from_slice(contents);
+        Ok(())
+    }
+
+    /// Erase a partition, setting it to 0xFF the same as erased flash
+    pub fn erase_partition(&mut self, partition: &Partition) -> Result<(), FlashError> {
+        if partition.is_readonly() {
+            return Err(FlashError::ReadOnly {
+                name: partition.name().to_owned(),
+            });
+        }
+
+        if partition.offset() % DATA_ALIGNMENT != 0 {
+            return Err(FlashError::Misaligned {
+                name: partition.name().to_owned(),
+                offset: partition.offset(),
+            });
+        }
+
+        let (start, end) = self.bounds(partition)?;
+        self.data[start..end].iter_mut().for_each(|b| *b = 0xff);
+        Ok(())
+    }
+
+    fn bounds(&self, partition: &Partition) -> Result<(usize, usize), FlashError> {
+        if partition.end() > self.data.len() as u64 {
+            return Err(FlashError::OutOfBounds {
+                name: partition.name().to_owned(),
+            });
+        }
+
+        Ok((partition.offset() as usize, partition.end() as usize))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::partition_table::{PartitionType, Subtype};
+
+    /// A 128K dump with the table at 0x8000 and each partition filled with a
+    /// byte of its own
+    fn dump() -> Vec<u8> {
+        let table = PartitionTable::from_csv(
+            "nvs,data,nvs,0x9000,0x4000\n\
+             cal,data,0x40,0xd000,0x1000,readonly\n\
+             app,app,factory,0x10000,0x10000\n",
+        )
+        .unwrap();
+
+        let table = table.to_bytes();
+        let mut data = vec![0u8; 0x20000];
+        data[0x8000..0x8000 + table.len()].copy_from_slice(&table);
+        data[0x9000..0xd000].iter_mut().for_each(|b| *b = 0x11);
+        data[0xd000..0xe000].iter_mut().for_each(|b| *b = 0x22);
+        data[0x10000..].iter_mut().for_each(|b| *b = 0x33);
+        data
+    }
+
+    #[test]
+    fn table_is_found_in_the_dump() {
+        let image = FlashImage::new(dump()).unwrap();
+        assert_eq!(image.table_offset(), 0x8000);
+        assert_eq!(image.table().partitions().len(), 3);
+
+        assert!(matches!(
+            FlashImage::with_table_offset(dump(), 0x9000),
+            Err(FlashError::Table { offset: 0x9000, .. })
+        ));
+        assert!(matches!(
+            FlashImage::with_table_offset(dump(), 0x40000),
+            Err(FlashError::TableNotFound)
+        ));
+    }
+
+    #[test]
+    fn read_write_and_erase() {
+        let mut image = FlashImage::new(dump()).unwrap();
+        let table = image.table().clone();
+        let nvs = table.find_by_name("nvs").unwrap();
+        let app = table.find_by_name("app").unwrap();
+
+        assert_eq!(image.read_partition(nvs).unwrap(), &[0x11; 0x4000][..]);
+
+        image.write_partition(app, &[0xab; 0x100]).unwrap();
+        let contents = image.read_partition(app).unwrap();
+        assert_eq!(contents[..0x100], [0xab; 0x100]);
+        assert!(contents[0x100..].iter().all(|b| *b == 0xff));
+
+        image.erase_partition(nvs).unwrap();
+        assert!(image
+            .read_partition(nvs)
+            .unwrap()
+            .iter()
+            .all(|b| *b == 0xff));
+        // the partitions around it are untouched
+        assert_eq!(image.data()[0x8fff], 0x00);
+        assert_eq!(image.data()[0xd000], 0x22);
+    }
+
+    #[test]
+    fn checks_before_writing() {
+        let mut image = FlashImage::new(dump()).unwrap();
+        let table = image.table().clone();
+        let cal = table.find_by_name("cal").unwrap();
+        let nvs = table.find_by_name("nvs").unwrap();
+        let custom = |offset, size| {
+            Partition::new(
+                "custom".to_owned(),
+                PartitionType::Data,
+                Subtype::Custom(0x40),
+                offset,
+                size,
+                0,
+            )
+        };
+
+        assert!(matches!(
+            image.write_partition(cal, &[0; 16]),
+            Err(FlashError::ReadOnly { .. })
+        ));
+        assert!(matches!(
+            image.erase_partition(cal),
+            Err(FlashError::ReadOnly { .. })
+        ));
+        assert!(matches!(
+            image.write_partition(nvs, &[0; 0x4001]),
+            Err(FlashError::TooLarge {
+                size: 0x4001,
+                max: 0x4000,
+                ..
+            })
+        ));
+        assert!(matches!(
+            image.erase_partition(&custom(0xe800, 0x800)),
+            Err(FlashError::Misaligned { offset: 0xe800, .. })
+        ));
+        assert!(matches!(
+            image.read_partition(&custom(0x1f000, 0x2000)),
+            Err(FlashError::OutOfBounds { .. })
+        ));
+        assert!(matches!(
+            image.write_partition(&custom(0x1f000, 0x2000), &[]),
+            Err(FlashError::OutOfBounds { .. })
+        ));
+
+        // nothing was changed by the rejected writes
+        assert_eq!(image.into_data(), dump());
+    }
+}
@@ -1,2 +1,3 @@
+pub mod flash;
 pub mod nvs;
 pub mod partition_table;
@@ -0,0 +1,21 @@
+/// CRC32 as used by the esp-idf for nvs pages and entries. This is the standard
+/// reflected CRC32 (polynomial 0xedb88320) seeded the same way the esp-idf calls
+/// `esp_rom_crc32_le(0xffffffff, ...)`, so the results will not match a plain
+/// crc32 of the same data.
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0xffffffff, data)
+}
+
+/// Continue a CRC32 calculation across multiple non-contiguous slices. `crc`
+/// should be the result of a previous call to `crc32` or `crc32_update`.
+pub(crate) fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,315 @@
+use std::convert::TryInto;
+use std::path::Path;
+
+use crate::nvs::error::{CsvError, CsvErrorKind};
+use crate::nvs::event::EntryType;
+use crate::nvs::generate::KeyValue;
+use crate::nvs::writer::MAX_KEY_LEN;
+
+/// Parse the `key,type,encoding,value` csv used by esp-idf's nvs_partition_gen.py
+/// into the values it describes.
+///
+/// ```text
+/// key,type,encoding,value
+/// storage,namespace,,
+/// serial,data,string,SN-0001
+/// calibration,file,binary,calibration.bin
+/// ```
+///
+/// A `namespace` row starts a namespace that the following rows are stored in.
+/// `data` rows hold their value inline while `file` rows name a file, relative
+/// to `base_dir`, that the value is read from. The encodings are the integer
+/// types `u8` to `i64`, `string`, `hex2bin` and `base64` which are decoded to a
+/// blob, and `binary` which stores the contents of a file as is. Blank lines,
+/// comments starting with `#` and the header row are skipped.
+pub fn parse_csv(input: &str, base_dir: &Path) -> Result<Vec<KeyValue>, CsvError> {
+    let mut items = vec![];
+    let mut namespace: Option<String> = None;
+
+    for (i, line) in input.lines().enumerate() {
+        let line_no = i + 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let err = |kind: CsvErrorKind| CsvError::new(line_no, kind);
+        let fields = split_fields(line);
+        if fields.len() > 4 {
+            return Err(err(CsvErrorKind::TooManyFields));
+        }
+        let field = |idx: usize| fields.get(idx).map(String::as_str).unwrap_or("");
+
+        let key = field(0);
+        if key.is_empty() {
+            return Err(err(CsvErrorKind::MissingField("key")));
+        }
+        if key == "key" && field(1) == "type" {
+            continue;
+        }
+        if key.len() > MAX_KEY_LEN {
+            return Err(err(CsvErrorKind::KeyTooLong(key.to_owned())));
+        }
+
+        let kind = field(1);
+        let encoding = field(2);
+        let value = field(3);
+        let data = match kind {
+            "namespace" => {
+                namespace = Some(key.to_owned());
+                continue;
+            }
+            "data" => {
+                if encoding.is_empty() {
+                    return Err(err(CsvErrorKind::MissingField("encoding")));
+                }
+                parse_value(encoding, value).map_err(err)?
+            }
+            "file" => {
+                if value.is_empty() {
+                    return Err(err(CsvErrorKind::MissingField("value")));
+                }
+                let path = base_dir.join(value);
+                let contents = std::fs::read(&path)
+                    .map_err(|source| err(CsvErrorKind::File { path, source }))?;
+                decode(encoding, &contents, true).map_err(err)?
+            }
+            "" => return Err(err(CsvErrorKind::MissingField("type"))),
+            _ => return Err(err(CsvErrorKind::InvalidType(kind.to_owned()))),
+        };
+
+        let namespace = namespace
+            .as_deref()
+            .ok_or_else(|| err(CsvErrorKind::MissingNamespace))?;
+        items.push(KeyValue::new(namespace, key, data));
+    }
+
+    Ok(items)
+}
+
+/// Convert a value written inline the same as the value of a `data` row, `encoding`
+/// is one of the integer types, `string`, `hex2bin` or `base64`
+pub fn parse_value(encoding: &str, value: &str) -> Result<EntryType, CsvErrorKind> {
+    decode(encoding, value.as_bytes(), false)
+}
+
+/// Split a line on commas, allowing fields to be quoted the same as python's
+/// csv module so values can contain commas
+fn split_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            '"' if quoted => quoted = false,
+            '"' if field.trim().is_empty() => {
+                field.clear();
+                quoted = true;
+            }
+            ',' if !quoted => fields.push(std::mem::take(&mut field).trim().to_owned()),
+            _ => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_owned());
+
+    fields
+}
+
+/// Convert a value to the type named by `encoding`. The contents of files are
+/// read as text for every encoding other than `binary`.
+fn decode(encoding: &str, value: &[u8], file: bool) -> Result<EntryType, CsvErrorKind> {
+    let invalid = || CsvErrorKind::InvalidValue(String::from_utf8_lossy(value).into_owned());
+    if encoding == "binary" {
+        if !file {
+            return Err(CsvErrorKind::InvalidEncoding(encoding.to_owned()));
+        }
+        return Ok(EntryType::Blob(value.to_vec()));
+    }
+
+    let text = std::str::from_utf8(value).map_err(|_| invalid())?;
+    let number = || parse_number(text.trim()).ok_or_else(invalid);
+    let data = match encoding {
+        "u8" => EntryType::U8(number()?.try_into().map_err(|_| invalid())?),
+        "i8" => EntryType::I8(number()?.try_into().map_err(|_| invalid())?),
+        "u16" => EntryType::U16(number()?.try_into().map_err(|_| invalid())?),
+        "i16" => EntryType::I16(number()?.try_into().map_err(|_| invalid())?),
+        "u32" => EntryType::U32(number()?.try_into().map_err(|_| invalid())?),
+        "i32" => EntryType::I32(number()?.try_into().map_err(|_| invalid())?),
+        "u64" => EntryType::U64(number()?.try_into().map_err(|_| invalid())?),
+        "i64" => EntryType::I64(number()?.try_into().map_err(|_| invalid())?),
+        "string" => EntryType::String(text.to_owned()),
+        "hex2bin" => EntryType::Blob(decode_hex(text.trim()).ok_or_else(invalid)?),
+        "base64" => EntryType::Blob(decode_base64(text.trim()).ok_or_else(invalid)?),
+        _ => return Err(CsvErrorKind::InvalidEncoding(encoding.to_owned())),
+    };
+
+    Ok(data)
+}
+
+/// Parse a decimal or `0x` prefixed hex number with an optional sign
+fn parse_number(value: &str) -> Option<i128> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+
+    // `from_str_radix` accepts a sign of its own, only digits may follow the prefix
+    let number = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) if hex.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            i128::from_str_radix(hex, 16).ok()?
+        }
+        None if value.bytes().all(|b| b.is_ascii_digit()) => value.parse::<i128>().ok()?,
+        _ => return None,
+    };
+
+    Some(if negative { -number } else { number })
+}
+
+fn decode_hex(value: &str) -> Option<Vec<u8>> {
+    if value.len() % 2 != 0 || !value.is_ascii() {
+        return None;
+    }
+
+    (0..value.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&value[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Decode padded base64, rejecting input that python's `base64.b64decode` would
+/// refuse: a length that isn't a multiple of four, padding other than one or two
+/// trailing `=` and unused bits that aren't zero
+fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    let value: Vec<u8> = value
+        .bytes()
+        .filter(|c| *c != b'\r' && *c != b'\n')
+        .collect();
+    let data_len = value.iter().rposition(|c| *c != b'=').map_or(0, |i| i + 1);
+    if value.len() % 4 != 0 || value.len() - data_len > 2 {
+        return None;
+    }
+
+    let mut bytes = vec![];
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &c in &value[..data_len] {
+        let sextet = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+
+        buffer = (buffer << 6) | sextet as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+
+    if buffer != 0 {
+        return None;
+    }
+
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_fields() {
+        let input = "key,type,encoding,value\n\
+                     config,namespace,,\n\
+                     greeting,data,string,\"hello, \"\"world\"\"\"\n\
+                     \"name\" , data , string , plain \n";
+        let items = parse_csv(input, Path::new("")).unwrap();
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].namespace(), "config");
+        assert!(matches!(items[0].value(), EntryType::String(s) if s == "hello, \"world\""));
+        assert_eq!(items[1].key(), "name");
+        assert!(matches!(items[1].value(), EntryType::String(s) if s == "plain"));
+    }
+
+    #[test]
+    fn files_are_relative_to_base_dir() {
+        let dir = std::env::temp_dir().join(format!("esp32-nvs-csv-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        std::fs::write(dir.join("data/cal.bin"), [0x00, 0xff, 0x10]).unwrap();
+        std::fs::write(dir.join("data/id.txt"), "0x2a\n").unwrap();
+
+        let found = parse_csv(
+            "factory,namespace,,\n\
+             cal,file,binary,data/cal.bin\n\
+             id,file,u16,data/id.txt\n",
+            &dir,
+        );
+        let missing = parse_csv("factory,namespace,,\nmissing,file,binary,cal.bin\n", &dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let found = found.unwrap();
+        assert!(matches!(found[0].value(), EntryType::Blob(b) if b == &[0x00, 0xff, 0x10]));
+        assert!(matches!(found[1].value(), EntryType::U16(0x2a)));
+        let err = missing.unwrap_err();
+        assert_eq!(err.line(), 2);
+        match err.kind() {
+            CsvErrorKind::File { path, .. } => assert_eq!(path, &dir.join("cal.bin")),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        let input = "key,type,encoding,value\n\
+                     \n\
+                     # a comment\n\
+                     wifi,namespace,,\n\
+                     channel,data,u8,300\n";
+        let err = parse_csv(input, Path::new("")).unwrap_err();
+        assert_eq!(err.line(), 5);
+        assert!(matches!(err.kind(), CsvErrorKind::InvalidValue(v) if v == "300"));
+        assert_eq!(err.to_string(), "line 5: invalid value '300'");
+
+        let err = parse_csv("orphan,data,i8,1\n", Path::new("")).unwrap_err();
+        assert_eq!(err.line(), 1);
+        assert!(matches!(err.kind(), CsvErrorKind::MissingNamespace));
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number("-0x10"), Some(-16));
+        assert_eq!(parse_number("+42"), Some(42));
+        assert_eq!(parse_number("0XfF"), Some(255));
+        for value in &["--5", "+-5", "0x-5", "0x+5", "-", "0x", "1_000"] {
+            assert_eq!(parse_number(value), None, "{}", value);
+        }
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(decode_base64("aGk=").unwrap(), b"hi");
+        assert_eq!(decode_base64("aGV5\r\nISE=").unwrap(), b"hey!!");
+        assert_eq!(decode_base64("").unwrap(), b"");
+        for value in &["A", "aGk", "aGl=", "aG==", "a===", "aG=k", "===="] {
+            assert!(decode_base64(value).is_none(), "{}", value);
+        }
+        assert!(matches!(
+            parse_value("base64", "A"),
+            Err(CsvErrorKind::InvalidValue(v)) if v == "A"
+        ));
+    }
+}
@@ -0,0 +1,287 @@
+use crate::nvs::error::{EditError, GenerateError};
+use crate::nvs::event::{Entry, EntryType};
+use crate::nvs::generate::MAX_NAMESPACES;
+use crate::nvs::nvs::{Nvs, PAGE_SIZE};
+use crate::nvs::page::{State, Version};
+use crate::nvs::writer::{PageWriter, Writer, MAX_KEY_LEN};
+
+/// The chunk index the firmware starts the second version of a blob at, a blob
+/// that is rewritten alternates between this and 0 so the old and new chunks can
+/// be told apart
+const CHUNK_VERSION_1: u8 = 128;
+
+/// Edits an existing nvs partition image the way the firmware would. New values
+/// are appended to the active page and the entries they replace are marked as
+/// erased, so the image keeps the same wear levelling history as one written on
+/// a device. Erased entries are not reclaimed, once no free page is left beyond
+/// the one the firmware keeps in reserve writes fail with `PartitionFull`.
+#[derive(Debug, Clone)]
+pub struct NvsEditor {
+    data: Vec<u8>,
+    writer: Writer,
+}
+
+impl NvsEditor {
+    pub fn new(data: Vec<u8>) -> Result<NvsEditor, EditError> {
+        let nvs = Nvs::parse(&data)?;
+        let pages: Vec<PageWriter> = nvs.pages().iter().map(PageWriter::from_page).collect();
+
+        // new pages use the format of the pages already written
+        let version = pages
+            .iter()
+            .filter(|page| *page.state() != State::Empty)
+            .max_by_key(|page| page.seq_no())
+            .map_or(Version::V2, |page| page.version());
+
+        Ok(NvsEditor {
+            writer: Writer::new(pages, version),
+            data,
+        })
+    }
+
+    /// The partition as it is after the edits, the pages that have not changed
+    /// are left exactly as they were read
+    pub fn data(&self) -> Vec<u8> {
+        let mut data = self.data.clone();
+        for (i, page) in self.writer.pages().iter().enumerate() {
+            if page.is_dirty() {
+                data[i * PAGE_SIZE..(i + 1) * PAGE_SIZE].copy_from_slice(&page.to_bytes());
+            }
+        }
+        data
+    }
+
+    /// Parse the edited partition
+    pub fn nvs(&self) -> Result<Nvs, EditError> {
+        Ok(Nvs::parse(&self.data())?)
+    }
+
+    /// Write `value` to `key`, creating the namespace if it does not exist yet.
+    /// Writing the value a key already has leaves the partition unchanged, the
+    /// same as the firmware. The partition is left as it was if the write fails.
+    pub fn set(&mut self, namespace: &str, key: &str, value: EntryType) -> Result<(), EditError> {
+        for name in [namespace, key] {
+            if name.len() > MAX_KEY_LEN {
+                return Err(GenerateError::KeyTooLong {
+                    key: name.to_owned(),
+                }
+                .into());
+            }
+        }
+
+        // the namespace is written before the value, don't leave it behind when
+        // the value can't be written
+        let writer = self.writer.clone();
+        let result = self.write(namespace, key, value);
+        if result.is_err() {
+            self.writer = writer;
+        }
+        result
+    }
+
+    fn write(&mut self, namespace: &str, key: &str, value: EntryType) -> Result<(), EditError> {
+        let nvs = self.nvs()?;
+        let ns = match nvs.namespace_index(namespace) {
+            Some(ns) => ns,
+            None => {
+                let ns = (1..=MAX_NAMESPACES as u8)
+                    .find(|ns| nvs.namespace_name(*ns).is_none())
+                    .ok_or(GenerateError::TooManyNamespaces)?;
+                self.writer
+                    .write_item(0, namespace, &EntryType::U8(ns), 0)?;
+                ns
+            }
+        };
+
+        let old = nvs
+            .namespace(namespace)
+            .and_then(|entries| entries.get(key).map(|entry| (*entry).clone()));
+        if let Some(old) = &old {
+            if *old.data() == value {
+                return Ok(());
+            }
+        }
+
+        let chunk_start = match old.as_ref().and_then(|old| old.chunks().first()) {
+            Some(chunk) if chunk.chunk_index() < CHUNK_VERSION_1 => CHUNK_VERSION_1,
+            _ => 0,
+        };
+        self.writer.write_item(ns, key, &value, chunk_start)?;
+
+        // the old value is only erased once the new one has been written
+        if let Some(old) = &old {
+            self.erase_entry(old);
+        }
+        Ok(())
+    }
+
+    /// Erase `key`, returning whether it existed
+    pub fn erase(&mut self, namespace: &str, key: &str) -> Result<bool, EditError> {
+        let nvs = self.nvs()?;
+        let entry = nvs
+            .namespace(namespace)
+            .and_then(|entries| entries.get(key).map(|entry| (*entry).clone()));
+
+        match entry {
+            Some(entry) => {
+                self.erase_entry(&entry);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Erase every key in the namespace, returning how many were erased. The
+    /// namespace itself is kept the same as `nvs_erase_all` in the firmware.
+    pub fn erase_namespace(&mut self, namespace: &str) -> Result<usize, EditError> {
+        let nvs = self.nvs()?;
+        let entries: Vec<Entry> = match nvs.namespace(namespace) {
+            Some(entries) => entries.values().map(|entry| (*entry).clone()).collect(),
+            None => return Ok(0),
+        };
+
+        for entry in &entries {
+            self.erase_entry(entry);
+        }
+        Ok(entries.len())
+    }
+
+    /// Erase the entries of an item along with the chunks of a version 2 blob
+    fn erase_entry(&mut self, entry: &Entry) {
+        self.writer.erase(
+            entry.page() as usize,
+            entry.start() as usize,
+            entry.end() as usize,
+        );
+        for chunk in entry.chunks() {
+            self.writer.erase(
+                chunk.page() as usize,
+                chunk.start() as usize,
+                chunk.end() as usize,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nvs::NvsGenerator;
+
+    fn editor() -> NvsEditor {
+        let mut generator = NvsGenerator::new(0x3000);
+        generator.add("ns", "a", EntryType::U8(1));
+        NvsEditor::new(generator.generate().unwrap()).unwrap()
+    }
+
+    fn value(editor: &NvsEditor, namespace: &str, key: &str) -> Option<EntryType> {
+        let nvs = editor.nvs().unwrap();
+        let entries = nvs.namespace(namespace)?;
+        entries.get(key).map(|entry| entry.data().clone())
+    }
+
+    #[test]
+    fn set_and_erase() {
+        let mut editor = editor();
+        editor.set("ns", "a", EntryType::U8(2)).unwrap();
+        editor
+            .set("other", "b", EntryType::String("b".to_owned()))
+            .unwrap();
+        assert_eq!(value(&editor, "ns", "a"), Some(EntryType::U8(2)));
+        assert_eq!(
+            value(&editor, "other", "b"),
+            Some(EntryType::String("b".to_owned()))
+        );
+
+        // the replaced value is erased rather than left as a duplicate
+        let nvs = editor.nvs().unwrap();
+        assert_eq!(nvs.entries().iter().filter(|e| e.key() == "a").count(), 1);
+
+        assert!(editor.erase("ns", "a").unwrap());
+        assert!(!editor.erase("ns", "a").unwrap());
+        assert_eq!(value(&editor, "ns", "a"), None);
+        assert_eq!(editor.erase_namespace("other").unwrap(), 1);
+        assert_eq!(value(&editor, "other", "b"), None);
+    }
+
+    #[test]
+    fn setting_the_same_value_is_a_no_op() {
+        let mut editor = editor();
+        let data = editor.data();
+        editor.set("ns", "a", EntryType::U8(1)).unwrap();
+        assert_eq!(editor.data(), data);
+    }
+
+    #[test]
+    fn rewritten_blob_alternates_chunk_start() {
+        let mut editor = editor();
+        let chunk_index = |editor: &NvsEditor| {
+            let nvs = editor.nvs().unwrap();
+            let entries = nvs.namespace("ns").unwrap();
+            entries["blob"].chunks()[0].chunk_index()
+        };
+
+        editor
+            .set("ns", "blob", EntryType::Blob(vec![1; 40]))
+            .unwrap();
+        assert_eq!(chunk_index(&editor), 0);
+        editor
+            .set("ns", "blob", EntryType::Blob(vec![2; 40]))
+            .unwrap();
+        assert_eq!(chunk_index(&editor), CHUNK_VERSION_1);
+        editor
+            .set("ns", "blob", EntryType::Blob(vec![3; 40]))
+            .unwrap();
+        assert_eq!(chunk_index(&editor), 0);
+        assert_eq!(
+            value(&editor, "ns", "blob"),
+            Some(EntryType::Blob(vec![3; 40]))
+        );
+    }
+
+    #[test]
+    fn writes_roll_over_to_the_next_page() {
+        let mut editor = editor();
+        // the first page holds the namespace and `a`, fill the rest of it
+        for i in 0..124 {
+            editor.set("ns", "a", EntryType::U32(i)).unwrap();
+        }
+        let nvs = editor.nvs().unwrap();
+        assert_eq!(*nvs.pages()[0].state(), State::Active);
+
+        editor.set("ns", "a", EntryType::U32(1000)).unwrap();
+        let nvs = editor.nvs().unwrap();
+        assert_eq!(*nvs.pages()[0].state(), State::Full);
+        assert_eq!(*nvs.pages()[1].state(), State::Active);
+        assert_eq!(nvs.pages()[1].seq_no(), 1);
+        assert_eq!(value(&editor, "ns", "a"), Some(EntryType::U32(1000)));
+
+        // the last page is kept free so the partition fills after the second
+        for i in 0..125 {
+            editor.set("ns", "a", EntryType::U32(i)).unwrap();
+        }
+        assert!(matches!(
+            editor.set("ns", "a", EntryType::U32(2000)),
+            Err(EditError::Write(GenerateError::PartitionFull))
+        ));
+        assert_eq!(*editor.nvs().unwrap().pages()[2].state(), State::Empty);
+    }
+
+    #[test]
+    fn failed_set_leaves_the_partition_unchanged() {
+        let mut editor = editor();
+        let data = editor.data();
+
+        let result = editor.set("new", "s", EntryType::String("a".repeat(4000)));
+        assert!(matches!(
+            result,
+            Err(EditError::Write(GenerateError::ValueTooLarge { .. }))
+        ));
+        assert!(matches!(
+            editor.set("new", "any", EntryType::Any),
+            Err(EditError::Write(GenerateError::UnsupportedType { .. }))
+        ));
+        assert_eq!(editor.data(), data);
+        assert_eq!(editor.nvs().unwrap().namespace_index("new"), None);
+    }
+}
@@ -0,0 +1,315 @@
+use std::fmt::Formatter;
+use std::path::PathBuf;
+
+use nom::error::{ErrorKind, FromExternalError, ParseError as NomParseError};
+
+use crate::nvs::page::{InvalidBitmapError, InvalidStateError};
+
+/// Errors that can occur while loading an nvs partition. Page and entry indices
+/// are zero based, entries being the 32 byte slots within a page.
+#[derive(Debug)]
+pub enum NvsError {
+    Io(std::io::Error),
+    /// The partition ends part way through a page
+    Truncated {
+        page: usize,
+    },
+    /// The page header has a state that is not defined by the esp-idf
+    InvalidState {
+        page: usize,
+        source: InvalidStateError,
+    },
+    /// The firmware marked the page as corrupted
+    CorruptedPage {
+        page: usize,
+    },
+    /// The entry state bitmap of the page contains an undefined state
+    InvalidBitmap {
+        page: usize,
+        source: InvalidBitmapError,
+    },
+    /// The entry has a type that is not defined by the esp-idf
+    UnknownEntryType {
+        page: usize,
+        entry: usize,
+        entry_type: u8,
+    },
+    /// The key of the entry is not valid utf-8
+    InvalidKey {
+        page: usize,
+        entry: usize,
+    },
+    /// The entry could not be parsed, for instance a span that runs past the end
+    /// of the page or a size that does not fit within the span
+    InvalidEntry {
+        page: usize,
+        entry: usize,
+    },
+    /// The entry is in the namespace table but does not hold a namespace index
+    InvalidNamespace {
+        page: usize,
+        entry: usize,
+    },
+    /// A page or entry failed crc verification while using `CrcPolicy::Error`
+    Crc {
+        page: usize,
+        entry: Option<usize>,
+    },
+}
+
+impl std::fmt::Display for NvsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "unable to read partition: {}", err),
+            Self::Truncated { page } => write!(f, "page {} is truncated", page),
+            Self::InvalidState { page, source } => write!(f, "page {}: {}", page, source),
+            Self::CorruptedPage { page } => write!(f, "page {} is marked as corrupted", page),
+            Self::InvalidBitmap { page, source } => write!(f, "page {}: {}", page, source),
+            Self::UnknownEntryType {
+                page,
+                entry,
+                entry_type,
+            } => write!(
+                f,
+                "entry {} on page {} has unknown type {:#04x}",
+                entry, page, entry_type
+            ),
+            Self::InvalidKey { page, entry } => {
+                write!(f, "entry {} on page {} has an invalid key", entry, page)
+            }
+            Self::InvalidEntry { page, entry } => {
+                write!(f, "entry {} on page {} is malformed", entry, page)
+            }
+            Self::InvalidNamespace { page, entry } => write!(
+                f,
+                "entry {} on page {} is not a valid namespace",
+                entry, page
+            ),
+            Self::Crc {
+                page,
+                entry: Some(entry),
+            } => write!(f, "entry {} on page {} has an invalid crc", entry, page),
+            Self::Crc { page, entry: None } => write!(f, "page {} has an invalid crc", page),
+        }
+    }
+}
+
+impl std::error::Error for NvsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            Self::InvalidState { source, .. } => Some(source),
+            Self::InvalidBitmap { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for NvsError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Errors that can occur while generating an nvs partition image
+#[derive(Debug, Clone)]
+pub enum GenerateError {
+    /// The size is not a multiple of the 4K page size or is less than 3 pages
+    InvalidSize { size: usize },
+    /// Keys and namespace names can be at most 15 bytes
+    KeyTooLong { key: String },
+    /// There are more than the 254 namespaces nvs supports
+    TooManyNamespaces,
+    /// The string or blob is too large to be stored
+    ValueTooLarge { key: String },
+    /// Blob chunks, blob indices and `Any` are written as part of a blob and can't
+    /// be stored directly
+    UnsupportedType { key: String },
+    /// The values do not fit in the partition
+    PartitionFull,
+}
+
+impl std::fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidSize { size } => write!(
+                f,
+                "partition size {:#x} must be a multiple of 0x1000 and at least 0x3000",
+                size
+            ),
+            Self::KeyTooLong { key } => write!(f, "key {} is longer than 15 bytes", key),
+            Self::TooManyNamespaces => write!(f, "too many namespaces, the maximum is 254"),
+            Self::ValueTooLarge { key } => write!(f, "value of {} is too large", key),
+            Self::UnsupportedType { key } => {
+                write!(f, "value of {} has a type that can't be written", key)
+            }
+            Self::PartitionFull => write!(f, "values do not fit in the partition"),
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+/// Errors that can occur while editing an nvs partition image
+#[derive(Debug)]
+pub enum EditError {
+    /// The existing partition could not be parsed
+    Nvs(NvsError),
+    /// The value could not be written to the partition
+    Write(GenerateError),
+}
+
+impl std::fmt::Display for EditError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Nvs(err) => write!(f, "{}", err),
+            Self::Write(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for EditError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Nvs(err) => Some(err),
+            Self::Write(err) => Some(err),
+        }
+    }
+}
+
+impl From<NvsError> for EditError {
+    fn from(err: NvsError) -> Self {
+        Self::Nvs(err)
+    }
+}
+
+impl From<GenerateError> for EditError {
+    fn from(err: GenerateError) -> Self {
+        Self::Write(err)
+    }
+}
+
+/// An error in an nvs_partition_gen csv and the line it occurred on
+#[derive(Debug)]
+pub struct CsvError {
+    line: usize,
+    kind: CsvErrorKind,
+}
+
+impl CsvError {
+    pub fn new(line: usize, kind: CsvErrorKind) -> CsvError {
+        CsvError { line, kind }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn kind(&self) -> &CsvErrorKind {
+        &self.kind
+    }
+}
+
+impl std::fmt::Display for CsvError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl std::error::Error for CsvError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match &self.kind {
+            CsvErrorKind::File { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CsvErrorKind {
+    /// The line ends before the named field
+    MissingField(&'static str),
+    /// There are more than the four key, type, encoding and value fields
+    TooManyFields,
+    /// Keys and namespace names can be at most 15 bytes
+    KeyTooLong(String),
+    /// The type is not one of namespace, data or file
+    InvalidType(String),
+    /// The encoding is unknown or can't be used with the type
+    InvalidEncoding(String),
+    /// The value can't be converted to the encoding
+    InvalidValue(String),
+    /// A value comes before the first namespace
+    MissingNamespace,
+    /// A file referenced by a file entry could not be read
+    File {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+impl std::fmt::Display for CsvErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingField(field) => write!(f, "missing {} field", field),
+            Self::TooManyFields => write!(f, "too many fields"),
+            Self::KeyTooLong(key) => write!(f, "key '{}' is longer than 15 bytes", key),
+            Self::InvalidType(value) => write!(f, "invalid type '{}'", value),
+            Self::InvalidEncoding(value) => write!(f, "invalid encoding '{}'", value),
+            Self::InvalidValue(value) => write!(f, "invalid value '{}'", value),
+            Self::MissingNamespace => write!(f, "value is not in a namespace"),
+            Self::File { path, source } => {
+                write!(f, "unable to read {}: {}", path.display(), source)
+            }
+        }
+    }
+}
+
+/// Error type used by the nom parsers. This carries enough detail to build an
+/// `NvsError` once the caller adds the page and entry the parser was run on.
+#[derive(Debug)]
+pub(crate) enum ParseError {
+    Nom,
+    State(InvalidStateError),
+    Bitmap(InvalidBitmapError),
+    UnknownEntryType(u8),
+    InvalidKey,
+}
+
+impl ParseError {
+    pub(crate) fn into_page_error(self, page: usize) -> NvsError {
+        match self {
+            Self::State(source) => NvsError::InvalidState { page, source },
+            Self::Bitmap(source) => NvsError::InvalidBitmap { page, source },
+            _ => NvsError::Truncated { page },
+        }
+    }
+
+    pub(crate) fn into_entry_error(self, page: usize, entry: usize) -> NvsError {
+        match self {
+            Self::UnknownEntryType(entry_type) => NvsError::UnknownEntryType {
+                page,
+                entry,
+                entry_type,
+            },
+            Self::InvalidKey => NvsError::InvalidKey { page, entry },
+            _ => NvsError::InvalidEntry { page, entry },
+        }
+    }
+}
+
+impl<I> NomParseError<I> for ParseError {
+    fn from_error_kind(_: I, _: ErrorKind) -> Self {
+        Self::Nom
+    }
+
+    fn append(_: I, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+}
+
+impl<I> FromExternalError<I, InvalidStateError> for ParseError {
+    fn from_external_error(_: I, _: ErrorKind, err: InvalidStateError) -> Self {
+        Self::State(err)
+    }
+}
@@ -16,9 +16,12 @@ pub struct Entry {
     page: u8,
     start: u8,
     end: u8,
+    chunks: Vec<BlobChunk>,
+    validity: Validity,
 }
 
 impl Entry {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         ns: u8,
         span: u8,
@@ -40,9 +43,24 @@ impl Entry {
             page,
             start,
             end,
+            chunks: vec![],
+            validity: Validity::Valid,
         }
     }
 
+    /// Set the result of verifying the crc32 values backing the entry
+    pub fn with_validity(mut self, validity: Validity) -> Entry {
+        self.validity = validity;
+        self
+    }
+
+    /// Attach the chunks that make up a multi-page blob to the entry for its
+    /// blob index
+    pub fn with_chunks(mut self, chunks: Vec<BlobChunk>) -> Entry {
+        self.chunks = chunks;
+        self
+    }
+
     pub fn ns(&self) -> u8 {
         self.ns
     }
@@ -78,10 +96,78 @@ impl Entry {
     pub fn end(&self) -> u8 {
         self.end
     }
+
+    /// The location of each chunk backing a multi-page blob in chunk order. This
+    /// is empty for every other type of entry.
+    pub fn chunks(&self) -> &[BlobChunk] {
+        &self.chunks
+    }
+
+    pub fn validity(&self) -> Validity {
+        self.validity
+    }
 }
 
-/// Representation of all types within the nvs spec mapped into Rust types
+/// Outcome of checking the crc32 values stored alongside pages and entries
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Validity {
+    Valid,
+    /// The crc32 stored in the page or entry header does not match the header
+    InvalidHeaderCrc,
+    /// The crc32 of a string or blob does not match its data
+    InvalidDataCrc,
+    /// The header crc32 is correct but the span or size of the entry runs past
+    /// the end of the page, its type is unknown or its key is not utf-8
+    Malformed,
+    /// The entry state bitmap holds the illegal state 0b01 for the entry
+    InvalidEntryState,
+}
+
+/// A single chunk (`BLOB_DATA` entry) of a multi-page blob and where it is
+/// stored on the partition
 #[derive(Debug, Clone)]
+pub struct BlobChunk {
+    chunk_index: u8,
+    size: usize,
+    page: u8,
+    start: u8,
+    end: u8,
+}
+
+impl BlobChunk {
+    pub fn new(chunk_index: u8, size: usize, page: u8, start: u8, end: u8) -> BlobChunk {
+        BlobChunk {
+            chunk_index,
+            size,
+            page,
+            start,
+            end,
+        }
+    }
+
+    pub fn chunk_index(&self) -> u8 {
+        self.chunk_index
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn page(&self) -> u8 {
+        self.page
+    }
+
+    pub fn start(&self) -> u8 {
+        self.start
+    }
+
+    pub fn end(&self) -> u8 {
+        self.end
+    }
+}
+
+/// Representation of all types within the nvs spec mapped into Rust types
+#[derive(Debug, Clone, PartialEq)]
 pub enum EntryType {
     U8(u8),
     I8(i8),
@@ -94,10 +180,36 @@ pub enum EntryType {
     String(String),
     Blob(Vec<u8>),
     BlobData(Vec<u8>),
-    BlobIndex,
+    BlobIndex {
+        size: u32,
+        chunk_count: u8,
+        chunk_start: u8,
+    },
     Any,
 }
 
+impl EntryType {
+    /// The name of the type, these match the encodings used by nvs_partition_gen.py
+    /// for integers and strings
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Self::U8(_) => "u8",
+            Self::I8(_) => "i8",
+            Self::U16(_) => "u16",
+            Self::I16(_) => "i16",
+            Self::U32(_) => "u32",
+            Self::I32(_) => "i32",
+            Self::U64(_) => "u64",
+            Self::I64(_) => "i64",
+            Self::String(_) => "string",
+            Self::Blob(_) => "blob",
+            Self::BlobData(_) => "blob_data",
+            Self::BlobIndex { .. } => "blob_index",
+            Self::Any => "any",
+        }
+    }
+}
+
 impl std::fmt::Display for EntryType {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -111,9 +223,17 @@ impl std::fmt::Display for EntryType {
             Self::I64(val) => write!(f, "{}", val),
             Self::String(val) => write!(f, "{}", val),
             Self::Blob(val) => write!(f, "{:?}", val),
-            Self::BlobData(_) => unimplemented!(),
-            Self::BlobIndex => unimplemented!(),
-            Self::Any => unreachable!(),
+            Self::BlobData(val) => write!(f, "{:?}", val),
+            Self::BlobIndex {
+                size,
+                chunk_count,
+                chunk_start,
+            } => write!(
+                f,
+                "size: {}, chunks: {}, start: {}",
+                size, chunk_count, chunk_start
+            ),
+            Self::Any => write!(f, ""),
         }
     }
 }
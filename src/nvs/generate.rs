@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+
+use crate::nvs::error::GenerateError;
+use crate::nvs::event::EntryType;
+use crate::nvs::nvs::PAGE_SIZE;
+use crate::nvs::page::{State, Version};
+use crate::nvs::writer::{PageWriter, Writer, MAX_KEY_LEN};
+
+/// The firmware needs at least this many pages, nvs_partition_gen.py has the
+/// same 0x3000 byte minimum
+const MIN_PAGES: usize = 3;
+/// Namespace indices are a u8 where 0 is the namespace table and 255 is unused
+pub(crate) const MAX_NAMESPACES: usize = 254;
+
+/// A single value to be stored in an nvs partition
+#[derive(Debug, Clone)]
+pub struct KeyValue {
+    namespace: String,
+    key: String,
+    value: EntryType,
+}
+
+impl KeyValue {
+    pub fn new(namespace: &str, key: &str, value: EntryType) -> KeyValue {
+        KeyValue {
+            namespace: namespace.to_owned(),
+            key: key.to_owned(),
+            value,
+        }
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+
+    pub fn value(&self) -> &EntryType {
+        &self.value
+    }
+}
+
+/// Builds an nvs partition image from namespaces and their values, the same as
+/// nvs_partition_gen.py. Values are written in the order they are added with
+/// each namespace being created the first time it is used.
+#[derive(Debug, Clone)]
+pub struct NvsGenerator {
+    size: usize,
+    version: Version,
+    items: Vec<KeyValue>,
+}
+
+impl NvsGenerator {
+    /// Start an image of `size` bytes using the version 2 page format
+    pub fn new(size: usize) -> NvsGenerator {
+        NvsGenerator {
+            size,
+            version: Version::V2,
+            items: vec![],
+        }
+    }
+
+    pub fn version(mut self, version: Version) -> NvsGenerator {
+        self.version = version;
+        self
+    }
+
+    /// Add a value to be written, `Blob` values are split into chunks when using
+    /// the version 2 format
+    pub fn add(&mut self, namespace: &str, key: &str, value: EntryType) -> &mut NvsGenerator {
+        self.items.push(KeyValue::new(namespace, key, value));
+        self
+    }
+
+    pub fn extend(&mut self, items: impl IntoIterator<Item = KeyValue>) -> &mut NvsGenerator {
+        self.items.extend(items);
+        self
+    }
+
+    pub fn items(&self) -> &[KeyValue] {
+        &self.items
+    }
+
+    /// Build the partition image. Written pages are marked full apart from the
+    /// last which is left active, and the final page of the partition is always
+    /// left empty as the firmware needs a free page to reclaim space.
+    pub fn generate(&self) -> Result<Vec<u8>, GenerateError> {
+        if self.size % PAGE_SIZE != 0 || self.size / PAGE_SIZE < MIN_PAGES {
+            return Err(GenerateError::InvalidSize { size: self.size });
+        }
+
+        let pages = vec![PageWriter::new(State::Empty, 0, self.version); self.size / PAGE_SIZE];
+        let mut writer = Writer::new(pages, self.version);
+        let mut namespaces: HashMap<&str, u8> = HashMap::new();
+
+        for item in &self.items {
+            for name in [&item.namespace, &item.key] {
+                if name.len() > MAX_KEY_LEN {
+                    return Err(GenerateError::KeyTooLong { key: name.clone() });
+                }
+            }
+
+            let ns = match namespaces.get(item.namespace.as_str()) {
+                Some(ns) => *ns,
+                None => {
+                    if namespaces.len() == MAX_NAMESPACES {
+                        return Err(GenerateError::TooManyNamespaces);
+                    }
+
+                    let ns = namespaces.len() as u8 + 1;
+                    writer.write_item(0, &item.namespace, &EntryType::U8(ns), 0)?;
+                    namespaces.insert(&item.namespace, ns);
+                    ns
+                }
+            };
+
+            writer.write_item(ns, &item.key, &item.value, 0)?;
+        }
+
+        let mut data = Vec::with_capacity(self.size);
+        for page in writer.pages() {
+            data.extend_from_slice(&page.to_bytes());
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nvs::writer::MAX_V1_BLOB_LEN;
+    use crate::nvs::{CrcPolicy, Nvs};
+
+    fn values() -> Vec<(&'static str, EntryType)> {
+        vec![
+            ("u8", EntryType::U8(0xab)),
+            ("i8", EntryType::I8(-2)),
+            ("u16", EntryType::U16(0xabcd)),
+            ("i16", EntryType::I16(-300)),
+            ("u32", EntryType::U32(0xdead_beef)),
+            ("i32", EntryType::I32(-70000)),
+            ("u64", EntryType::U64(0x0123_4567_89ab_cdef)),
+            ("i64", EntryType::I64(-5_000_000_000)),
+            ("string", EntryType::String("hello world".to_owned())),
+            ("empty_string", EntryType::String(String::new())),
+            ("blob", EntryType::Blob((0..100).collect())),
+            ("empty_blob", EntryType::Blob(vec![])),
+        ]
+    }
+
+    /// Generate an image holding `values` and parse it back
+    fn round_trip(version: Version, size: usize, values: &[(&str, EntryType)]) -> Nvs {
+        let mut generator = NvsGenerator::new(size).version(version);
+        for (key, value) in values {
+            generator.add("ns", key, value.clone());
+        }
+        let data = generator.generate().unwrap();
+        assert_eq!(data.len(), size);
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Error).unwrap();
+        let entries = nvs.namespace("ns").unwrap();
+        assert_eq!(entries.len(), values.len());
+        for (key, value) in values {
+            assert_eq!(entries[key].data(), value, "{}", key);
+        }
+        nvs
+    }
+
+    #[test]
+    fn round_trip_v1() {
+        round_trip(Version::V1, 0x3000, &values());
+    }
+
+    #[test]
+    fn round_trip_v2() {
+        round_trip(Version::V2, 0x3000, &values());
+    }
+
+    #[test]
+    fn multi_page_blob() {
+        let blob: Vec<u8> = (0..10000).map(|i| i as u8).collect();
+        let nvs = round_trip(Version::V2, 0x6000, &[("blob", EntryType::Blob(blob))]);
+        let entries = nvs.namespace("ns").unwrap();
+        assert_eq!(entries["blob"].chunks().len(), 3);
+    }
+
+    #[test]
+    fn v1_blob_size_limit() {
+        let blob = EntryType::Blob(vec![0; MAX_V1_BLOB_LEN]);
+        round_trip(Version::V1, 0x3000, &[("blob", blob)]);
+
+        let mut generator = NvsGenerator::new(0x3000).version(Version::V1);
+        generator.add("ns", "blob", EntryType::Blob(vec![0; MAX_V1_BLOB_LEN + 1]));
+        assert!(matches!(
+            generator.generate(),
+            Err(GenerateError::ValueTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_items() {
+        let generate = |namespace: &str, key: &str, value: EntryType| {
+            let mut generator = NvsGenerator::new(0x3000);
+            generator.add(namespace, key, value);
+            generator.generate()
+        };
+
+        assert!(matches!(
+            generate("ns", "a_key_that_is_too_long", EntryType::U8(0)),
+            Err(GenerateError::KeyTooLong { .. })
+        ));
+        assert!(matches!(
+            generate("ns", "s", EntryType::String("a".repeat(4000))),
+            Err(GenerateError::ValueTooLarge { .. })
+        ));
+        assert!(matches!(
+            generate("ns", "any", EntryType::Any),
+            Err(GenerateError::UnsupportedType { .. })
+        ));
+        assert!(matches!(
+            NvsGenerator::new(0x2000).generate(),
+            Err(GenerateError::InvalidSize { .. })
+        ));
+    }
+}
@@ -1,6 +1,18 @@
+mod crc;
+mod csv;
+mod edit;
+mod error;
 pub mod event;
+mod generate;
+#[allow(clippy::module_inception)]
 mod nvs;
 mod page;
 mod parsers;
+mod writer;
 
-pub use nvs::Nvs;
+pub use csv::{parse_csv, parse_value};
+pub use edit::NvsEditor;
+pub use error::{CsvError, CsvErrorKind, EditError, GenerateError, NvsError};
+pub use generate::{KeyValue, NvsGenerator};
+pub use nvs::{CrcPolicy, Nvs};
+pub use page::{InvalidBitmapError, InvalidStateError, Version};
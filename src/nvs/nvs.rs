@@ -1,11 +1,29 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::Read;
 
-use nom::multi::many0;
+use crate::nvs::error::{NvsError, ParseError};
+use crate::nvs::event::{BlobChunk, Entry, EntryType, Validity};
+use crate::nvs::page::{EntryStateBitmap, InvalidBitmapError, Page, State};
 
-use crate::nvs::event::{Entry, EntryType};
-use crate::nvs::page::{EntryStateBitmap, Page};
+pub(crate) const PAGE_SIZE: usize = 4096;
+/// Number of 32 byte entries in a page, the first two slots are taken up by the
+/// page header and entry state bitmap
+pub(crate) const ENTRY_COUNT: usize = 126;
+
+/// How pages and entries that fail crc32 verification, whose span does not fit
+/// in the page, whose type is unknown or whose entry state is illegal are handled
+/// while loading a partition. Pages marked as corrupted, or with a state that is
+/// not defined, are handled the same way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CrcPolicy {
+    /// Leave corrupt pages and entries out, the same as the firmware would
+    Skip,
+    /// Keep corrupt pages and entries, they can be identified by their validity
+    Report,
+    /// Refuse to load a partition containing any corruption
+    Error,
+}
 
 #[derive(Debug, Clone)]
 pub struct Nvs {
@@ -18,79 +36,231 @@ pub struct Nvs {
 }
 
 impl Nvs {
-    pub fn new(file: &str, use_deleted: bool) -> Nvs {
-        let mut f = File::open(file).unwrap();
+    /// Load an nvs partition from a file, see `parse_with` for the options
+    pub fn new(file: &str, use_deleted: bool, crc_policy: CrcPolicy) -> Result<Nvs, NvsError> {
+        let mut f = File::open(file)?;
         let mut data = vec![];
-        f.read_to_end(&mut data).unwrap();
+        f.read_to_end(&mut data)?;
+
+        Nvs::parse_with(&data, use_deleted, crc_policy)
+    }
 
-        let (_, pages) = many0(crate::nvs::parsers::page)(&data).unwrap();
+    /// Parse an nvs partition the same way the firmware would view it, ignoring
+    /// deleted entries and anything that fails crc verification. Pages are read
+    /// in order of their sequence number and follow the rules the firmware applies
+    /// when it initializes nvs:
+    ///
+    /// * uninitialized and corrupted pages hold no entries
+    /// * a page left in the erasing state was being reclaimed, its entries are
+    ///   still current and the active page they were being copied to is ignored
+    /// * when a key is written more than once only the newest entry is kept, this
+    ///   happens when power is lost before the old entry was erased
+    pub fn parse(data: &[u8]) -> Result<Nvs, NvsError> {
+        Nvs::parse_with(data, false, CrcPolicy::Skip)
+    }
+
+    /// Parse an nvs partition. `use_deleted` includes entries that have been
+    /// erased but are still present on the partition and `crc_policy` controls
+    /// what happens to corrupt pages and entries.
+    pub fn parse_with(
+        data: &[u8],
+        use_deleted: bool,
+        crc_policy: CrcPolicy,
+    ) -> Result<Nvs, NvsError> {
+        let mut pages = vec![];
+        for (i, raw) in data.chunks(PAGE_SIZE).enumerate() {
+            let page = match crate::nvs::parsers::page(raw) {
+                // the firmware treats a page with an unknown state as corrupted
+                Err(nom::Err::Error(ParseError::State(_))) if crc_policy != CrcPolicy::Error => {
+                    crate::nvs::parsers::page_with_state(raw, State::Corrupted)
+                }
+                page => page,
+            };
+            let (_, page) = page.map_err(|err| match err {
+                nom::Err::Error(err) | nom::Err::Failure(err) => err.into_page_error(i),
+                nom::Err::Incomplete(_) => NvsError::Truncated { page: i },
+            })?;
+            pages.push(page);
+        }
 
-        let mut entries = vec![];
+        let mut entries: Vec<Entry> = vec![];
+        let mut items = vec![];
+        let mut chunks = vec![];
         let mut namespaces: HashMap<u8, Vec<usize>> = HashMap::new();
         let mut namespace_lookup: HashMap<u8, String> = HashMap::new();
         let mut name_to_ns = HashMap::new();
 
-        for (i, page) in pages.iter().enumerate() {
-            let mut page_data = page.data();
-            let mut start = 0;
+        // corrupted pages hold no entries for the firmware, when they are kept they
+        // come first so anything on them is older than the pages in use
+        let order: Vec<usize> = (0..pages.len())
+            .filter(|idx| *pages[*idx].state() == State::Corrupted)
+            .chain(page_order(&pages))
+            .collect();
+        for i in order {
+            let page = &pages[i];
+            if *page.state() == State::Corrupted {
+                match crc_policy {
+                    CrcPolicy::Skip => continue,
+                    CrcPolicy::Report => {}
+                    CrcPolicy::Error => return Err(NvsError::CorruptedPage { page: i }),
+                }
+            }
+            if page.validity() != Validity::Valid {
+                match crc_policy {
+                    CrcPolicy::Skip => continue,
+                    CrcPolicy::Report => {}
+                    CrcPolicy::Error => {
+                        return Err(NvsError::Crc {
+                            page: i,
+                            entry: None,
+                        })
+                    }
+                }
+            }
+
+            let page_data = page.data();
             let bitmaps = page.entry_state_bitmap();
-            while page_data.len() > 0 {
+            let mut start = 0;
+            while start < ENTRY_COUNT {
                 if bitmaps[start] == EntryStateBitmap::Empty
                     || (bitmaps[start] == EntryStateBitmap::Erased && !use_deleted)
                 {
                     start += 1;
-                    page_data = &page_data[32..];
                     continue;
                 }
 
-                let (remainder, entry) =
-                    crate::nvs::parsers::entry(page_data, i as u8, start as u8).unwrap();
-                page_data = remainder;
+                // the entry can't be trusted when its state is illegal
+                let illegal = bitmaps[start] == EntryStateBitmap::Illegal;
+                if illegal {
+                    match crc_policy {
+                        CrcPolicy::Skip => {
+                            start += 1;
+                            continue;
+                        }
+                        CrcPolicy::Report => {}
+                        CrcPolicy::Error => {
+                            return Err(NvsError::InvalidBitmap {
+                                page: i,
+                                source: InvalidBitmapError::new(EntryStateBitmap::Illegal.value()),
+                            })
+                        }
+                    }
+                }
+
+                let (_, entry) = crate::nvs::parsers::entry(
+                    &page_data[start * 32..],
+                    i as u8,
+                    start as u8,
+                    crc_policy,
+                )
+                .map_err(|err| match err {
+                    nom::Err::Error(err) | nom::Err::Failure(err) => err.into_entry_error(i, start),
+                    nom::Err::Incomplete(_) => NvsError::InvalidEntry {
+                        page: i,
+                        entry: start,
+                    },
+                })?;
+                let entry_idx = start;
                 start = entry.end() as usize;
+                let entry = match entry.validity() {
+                    Validity::Valid if illegal => entry.with_validity(Validity::InvalidEntryState),
+                    _ => entry,
+                };
+
+                if entry.validity() != Validity::Valid {
+                    match crc_policy {
+                        CrcPolicy::Skip => continue,
+                        CrcPolicy::Report => {}
+                        CrcPolicy::Error if entry.validity() == Validity::Malformed => {
+                            return Err(NvsError::InvalidEntry {
+                                page: i,
+                                entry: entry_idx,
+                            })
+                        }
+                        CrcPolicy::Error => {
+                            return Err(NvsError::Crc {
+                                page: i,
+                                entry: Some(entry_idx),
+                            })
+                        }
+                    }
+                }
 
                 if entry.ns() == 0 {
-                    let key = match entry.data() {
-                        EntryType::U8(val) => val,
-                        _ => panic!("invalid type for namespace"),
-                    };
-
-                    if let EntryType::U8(ns_id) = entry.data() {
-                        namespace_lookup.insert(*ns_id, entry.key().to_owned());
-                        namespaces.insert(*ns_id, vec![]);
-                        name_to_ns.insert(entry.key().to_owned(), *ns_id);
-                        continue;
-                    } else {
-                        panic!("invalid type for ns");
+                    match entry.data() {
+                        EntryType::U8(ns_id) => {
+                            namespace_lookup.insert(*ns_id, entry.key().to_owned());
+                            namespaces.insert(*ns_id, vec![]);
+                            name_to_ns.insert(entry.key().to_owned(), *ns_id);
+                        }
+                        // a corrupt header can't be trusted to be a namespace at all
+                        _ if entry.validity() == Validity::InvalidHeaderCrc => {}
+                        _ => {
+                            return Err(NvsError::InvalidNamespace {
+                                page: i,
+                                entry: entry_idx,
+                            })
+                        }
                     }
+                } else if let EntryType::BlobData(_) = entry.data() {
+                    chunks.push(entry);
                 } else {
-                    entries.push(entry);
-                    match namespaces.get_mut(&entries.last().unwrap().ns()) {
-                        Some(ns) => ns.push(entries.len() - 1),
-                        None => println!("ns {} does not exist", entries.last().unwrap().key()),
-                    }
+                    items.push(entry);
                 }
             }
         }
 
-        Nvs {
+        // erased entries are only kept when asked for, so duplicates are left in
+        // place to show the full history of a key
+        if !use_deleted {
+            items = latest(items);
+            chunks = latest(chunks);
+        }
+
+        for entry in items {
+            let entry = match entry.data() {
+                EntryType::BlobIndex { .. } => match assemble_blob(&entry, &chunks) {
+                    Some(blob) => blob,
+                    // the firmware treats a blob with missing chunks as not existing
+                    None => continue,
+                },
+                _ => entry,
+            };
+
+            // entries whose namespace no longer exists are still available
+            // through `entries` but can't be looked up by namespace
+            if let Some(ns) = namespaces.get_mut(&entry.ns()) {
+                ns.push(entries.len());
+            }
+            entries.push(entry);
+        }
+
+        Ok(Nvs {
             pages,
             entries,
             namespace_lookup,
             namespaces,
             name_to_ns,
             use_deleted,
-        }
+        })
     }
 
     pub fn namespaces(&self) -> Vec<&str> {
         self.namespace_lookup.values().map(|v| v.as_str()).collect()
     }
 
+    /// The name of the namespace with index `ns`
+    pub fn namespace_name(&self, ns: u8) -> Option<&str> {
+        self.namespace_lookup.get(&ns).map(|name| name.as_str())
+    }
+
+    /// The index of the namespace named `name`
+    pub fn namespace_index(&self, name: &str) -> Option<u8> {
+        self.name_to_ns.get(name).copied()
+    }
+
     pub fn namespace(&self, ns: &str) -> Option<HashMap<&str, &Entry>> {
-        let ns_idx = match self.name_to_ns.get(ns) {
-            Some(idx) => idx,
-            None => return None,
-        };
+        let ns_idx = self.name_to_ns.get(ns)?;
 
         match self.namespaces.get(ns_idx) {
             Some(entry_ids) => {
@@ -112,4 +282,380 @@ impl Nvs {
     pub fn pages(&self) -> &[Page] {
         &self.pages
     }
+
+    pub fn use_deleted(&self) -> bool {
+        self.use_deleted
+    }
+}
+
+/// The indices of the pages holding entries, ordered from oldest to newest
+fn page_order(pages: &[Page]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..pages.len())
+        .filter(|idx| !matches!(pages[*idx].state(), State::Empty | State::Corrupted))
+        .collect();
+    order.sort_by_key(|idx| pages[*idx].seq_no());
+
+    // an erasing page had its entries part way through being copied to a newly
+    // activated page. The firmware discards the copies and copies the entries
+    // again, making the erasing page the newest.
+    if let Some(pos) = order
+        .iter()
+        .position(|idx| *pages[*idx].state() == State::Erasing)
+    {
+        let erasing = order.remove(pos);
+        if let Some(last) = order.last() {
+            if *pages[*last].state() == State::Active {
+                order.pop();
+            }
+        }
+        order.push(erasing);
+    }
+
+    order
+}
+
+/// Keep only the newest of entries that have the same namespace, key, type and
+/// chunk index, entries are given oldest first
+fn latest(entries: Vec<Entry>) -> Vec<Entry> {
+    let mut seen = HashSet::new();
+    let mut latest: Vec<Entry> = entries
+        .into_iter()
+        .rev()
+        .filter(|entry| {
+            seen.insert((
+                entry.ns(),
+                entry.key().to_owned(),
+                std::mem::discriminant(entry.data()),
+                entry.chunk_index(),
+            ))
+        })
+        .collect();
+    latest.reverse();
+    latest
+}
+
+/// Rebuild a multi-page blob from its index entry and the `BLOB_DATA` chunks
+/// belonging to it. Chunks are matched on namespace, key and the chunk version
+/// (`chunk_start`) recorded in the index. `None` is returned when chunks are
+/// missing or their combined size does not match the index.
+fn assemble_blob(index: &Entry, chunks: &[Entry]) -> Option<Entry> {
+    let (size, chunk_count, chunk_start) = match index.data() {
+        EntryType::BlobIndex {
+            size,
+            chunk_count,
+            chunk_start,
+        } => (*size, *chunk_count, *chunk_start),
+        _ => return None,
+    };
+
+    let mut data = vec![];
+    let mut locations = vec![];
+    let mut validity = index.validity();
+    for chunk_index in chunk_start..chunk_start.checked_add(chunk_count)? {
+        let chunk = chunks.iter().find(|chunk| {
+            chunk.ns() == index.ns()
+                && chunk.key() == index.key()
+                && chunk.chunk_index() == chunk_index
+        })?;
+
+        if let EntryType::BlobData(chunk_data) = chunk.data() {
+            if chunk.validity() != Validity::Valid {
+                validity = chunk.validity();
+            }
+
+            data.extend_from_slice(chunk_data);
+            locations.push(BlobChunk::new(
+                chunk_index,
+                chunk_data.len(),
+                chunk.page(),
+                chunk.start(),
+                chunk.end(),
+            ));
+        }
+    }
+
+    if data.len() != size as usize {
+        return None;
+    }
+
+    let blob = Entry::new(
+        index.ns(),
+        index.span(),
+        index.chunk_index(),
+        index.crc32(),
+        index.key().to_owned(),
+        EntryType::Blob(data),
+        index.page(),
+        index.start(),
+        index.end(),
+    );
+
+    Some(blob.with_chunks(locations).with_validity(validity))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nvs::crc;
+    use crate::nvs::NvsGenerator;
+
+    /// Offset of entry `slot` on the first page
+    fn entry_offset(slot: usize) -> usize {
+        64 + slot * 32
+    }
+
+    /// An image with a namespace entry in slot 0 followed by `a` and `b`
+    fn image() -> Vec<u8> {
+        let mut generator = NvsGenerator::new(0x3000);
+        generator
+            .add("ns", "a", EntryType::U8(1))
+            .add("ns", "b", EntryType::U8(2));
+        generator.generate().unwrap()
+    }
+
+    fn update_crc(data: &mut [u8], slot: usize) {
+        let entry = &data[entry_offset(slot)..entry_offset(slot) + 32];
+        let crc32 = crc::crc32_update(crc::crc32(&entry[0..4]), &entry[8..32]);
+        data[entry_offset(slot) + 4..entry_offset(slot) + 8].copy_from_slice(&crc32.to_le_bytes());
+    }
+
+    #[test]
+    fn corrupt_key_follows_crc_policy() {
+        let mut data = image();
+        // a key that is not utf-8 and no longer matches the header crc
+        data[entry_offset(1) + 8] = 0xff;
+        data[entry_offset(1) + 9] = 0xfe;
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Skip).unwrap();
+        let keys: Vec<&str> = nvs.entries().iter().map(|entry| entry.key()).collect();
+        assert_eq!(keys, ["b"]);
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Report).unwrap();
+        assert_eq!(nvs.entries().len(), 2);
+        assert_eq!(nvs.entries()[0].validity(), Validity::InvalidHeaderCrc);
+        assert_eq!(nvs.entries()[1].validity(), Validity::Valid);
+
+        match Nvs::parse_with(&data, false, CrcPolicy::Error) {
+            Err(NvsError::Crc {
+                page: 0,
+                entry: Some(1),
+            }) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn corrupt_data_follows_crc_policy() {
+        let mut generator = NvsGenerator::new(0x3000);
+        generator.add("ns", "s", EntryType::String("hello".to_owned()));
+        let mut data = generator.generate().unwrap();
+        // the string data is in the slot after its header
+        data[entry_offset(2)] ^= 0x01;
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Skip).unwrap();
+        assert!(nvs.entries().is_empty());
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Report).unwrap();
+        assert_eq!(nvs.entries()[0].validity(), Validity::InvalidDataCrc);
+
+        assert!(matches!(
+            Nvs::parse_with(&data, false, CrcPolicy::Error),
+            Err(NvsError::Crc { .. })
+        ));
+    }
+
+    #[test]
+    fn span_past_end_of_page_is_corrupt() {
+        let mut data = image();
+        data[entry_offset(2) + 2] = 200;
+        update_crc(&mut data, 2);
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Skip).unwrap();
+        let keys: Vec<&str> = nvs.entries().iter().map(|entry| entry.key()).collect();
+        assert_eq!(keys, ["a"]);
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Report).unwrap();
+        assert_eq!(nvs.entries()[1].validity(), Validity::Malformed);
+
+        assert!(matches!(
+            Nvs::parse_with(&data, false, CrcPolicy::Error),
+            Err(NvsError::InvalidEntry { page: 0, entry: 2 })
+        ));
+    }
+
+    #[test]
+    fn key_that_is_not_utf8_follows_crc_policy() {
+        let mut data = image();
+        data[entry_offset(2) + 8] = 0xc3;
+        update_crc(&mut data, 2);
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Skip).unwrap();
+        assert_eq!(nvs.entries().len(), 1);
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Report).unwrap();
+        assert_eq!(nvs.entries()[1].key(), "\u{fffd}");
+        assert_eq!(nvs.entries()[1].validity(), Validity::Malformed);
+
+        assert!(matches!(
+            Nvs::parse_with(&data, false, CrcPolicy::Error),
+            Err(NvsError::InvalidKey { page: 0, entry: 2 })
+        ));
+    }
+
+    #[test]
+    fn unknown_type_follows_crc_policy() {
+        let mut data = image();
+        data[entry_offset(1) + 1] = 0x99;
+        update_crc(&mut data, 1);
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Skip).unwrap();
+        let keys: Vec<&str> = nvs.entries().iter().map(|entry| entry.key()).collect();
+        assert_eq!(keys, ["b"]);
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Report).unwrap();
+        assert_eq!(nvs.entries()[0].key(), "a");
+        assert_eq!(nvs.entries()[0].validity(), Validity::Malformed);
+
+        assert!(matches!(
+            Nvs::parse_with(&data, false, CrcPolicy::Error),
+            Err(NvsError::UnknownEntryType {
+                page: 0,
+                entry: 1,
+                entry_type: 0x99,
+            })
+        ));
+    }
+
+    #[test]
+    fn illegal_entry_state_follows_crc_policy() {
+        let mut data = image();
+        // mark slot 1, bits 2..4 of the first bitmap byte, with the undefined state 0b01
+        data[32] = (data[32] & !0b1100) | 0b0100;
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Skip).unwrap();
+        let keys: Vec<&str> = nvs.entries().iter().map(|entry| entry.key()).collect();
+        assert_eq!(keys, ["b"]);
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Report).unwrap();
+        assert_eq!(nvs.entries()[0].validity(), Validity::InvalidEntryState);
+        assert_eq!(nvs.entries()[1].validity(), Validity::Valid);
+
+        match Nvs::parse_with(&data, false, CrcPolicy::Error) {
+            Err(NvsError::InvalidBitmap { page: 0, source }) => assert_eq!(source.value(), 0b01),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn string_is_read_across_the_following_slots() {
+        // 49 bytes and the nul terminator take up two data slots after the header
+        let hostname = "sensor-0042.greenhouse.example.internal.local.lan";
+        let mut generator = NvsGenerator::new(0x3000);
+        generator.add("net", "hostname", EntryType::String(hostname.to_owned()));
+        let data = generator.generate().unwrap();
+
+        let nvs = Nvs::parse_with(&data, true, CrcPolicy::Error).unwrap();
+        assert!(nvs.use_deleted());
+        let entry = nvs.namespace("net").unwrap()["hostname"];
+        assert_eq!(entry.data(), &EntryType::String(hostname.to_owned()));
+        assert_eq!((entry.start(), entry.span()), (1, 3));
+
+        let page = &nvs.pages()[0];
+        assert_eq!(page.unused(), &data[9..28]);
+        assert_eq!(page.crc32(), crc::crc32(&data[4..28]));
+    }
+
+    /// A page holding the namespace and `a` set to `value` with the given header
+    fn page(value: u8, state: State, seq_no: u32) -> Vec<u8> {
+        let mut generator = NvsGenerator::new(0x3000);
+        generator.add("ns", "a", EntryType::U8(value));
+        let mut data = generator.generate().unwrap();
+        data.truncate(PAGE_SIZE);
+        data[0..4].copy_from_slice(&state.value().to_le_bytes());
+        data[4..8].copy_from_slice(&seq_no.to_le_bytes());
+        let crc32 = crc::crc32(&data[4..28]);
+        data[28..32].copy_from_slice(&crc32.to_le_bytes());
+        data
+    }
+
+    fn value_of_a(data: &[u8]) -> EntryType {
+        let nvs = Nvs::parse_with(data, false, CrcPolicy::Error).unwrap();
+        let entries = nvs.namespace("ns").unwrap();
+        entries["a"].data().clone()
+    }
+
+    #[test]
+    fn pages_are_ordered_by_seq_no() {
+        let data = [
+            page(2, State::Active, 1),
+            page(1, State::Full, 0),
+            vec![0xff; PAGE_SIZE],
+        ]
+        .concat();
+        assert_eq!(value_of_a(&data), EntryType::U8(2));
+    }
+
+    #[test]
+    fn erasing_page_replaces_the_page_it_was_copied_to() {
+        let data = [
+            page(1, State::Erasing, 0),
+            page(2, State::Active, 1),
+            vec![0xff; PAGE_SIZE],
+        ]
+        .concat();
+        assert_eq!(value_of_a(&data), EntryType::U8(1));
+    }
+
+    /// Keys of the entries in the partition and the page each is on
+    fn keys(data: &[u8], crc_policy: CrcPolicy) -> Vec<(String, u8)> {
+        let nvs = Nvs::parse_with(data, false, crc_policy).unwrap();
+        let mut keys: Vec<(String, u8)> = nvs
+            .entries()
+            .iter()
+            .map(|entry| (entry.key().to_owned(), entry.page()))
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn corrupted_page_follows_crc_policy() {
+        let mut corrupted = page(2, State::Corrupted, 1);
+        // a key only found on the corrupted page
+        corrupted[entry_offset(1) + 8] = b'c';
+        update_crc(&mut corrupted, 1);
+        let data = [page(1, State::Active, 0), corrupted, vec![0xff; PAGE_SIZE]].concat();
+
+        assert_eq!(keys(&data, CrcPolicy::Skip), [("a".to_owned(), 0)]);
+        assert_eq!(
+            keys(&data, CrcPolicy::Report),
+            [("a".to_owned(), 0), ("c".to_owned(), 1)]
+        );
+        assert!(matches!(
+            Nvs::parse_with(&data, false, CrcPolicy::Error),
+            Err(NvsError::CorruptedPage { page: 1 })
+        ));
+    }
+
+    #[test]
+    fn unknown_page_state_follows_crc_policy() {
+        let mut unknown = page(2, State::Full, 1);
+        unknown[entry_offset(1) + 8] = b'c';
+        update_crc(&mut unknown, 1);
+        // the state is not covered by the header crc
+        unknown[0..4].copy_from_slice(&0x12u32.to_le_bytes());
+        let data = [page(1, State::Active, 0), unknown, vec![0xff; PAGE_SIZE]].concat();
+
+        let nvs = Nvs::parse_with(&data, false, CrcPolicy::Skip).unwrap();
+        assert_eq!(*nvs.pages()[1].state(), State::Corrupted);
+        assert_eq!(keys(&data, CrcPolicy::Skip), [("a".to_owned(), 0)]);
+        assert_eq!(
+            keys(&data, CrcPolicy::Report),
+            [("a".to_owned(), 0), ("c".to_owned(), 1)]
+        );
+        assert!(matches!(
+            Nvs::parse_with(&data, false, CrcPolicy::Error),
+            Err(NvsError::InvalidState { page: 1, .. })
+        ));
+    }
 }
@@ -1,5 +1,7 @@
 use std::convert::TryFrom;
 
+use crate::nvs::event::Validity;
+
 #[derive(Debug, Clone)]
 pub struct Page {
     state: State,
@@ -9,6 +11,7 @@ pub struct Page {
     crc32: u32,
     entry_state_bitmap: Vec<EntryStateBitmap>,
     data: Vec<u8>,
+    validity: Validity,
 }
 
 impl Page {
@@ -29,13 +32,36 @@ impl Page {
             crc32,
             entry_state_bitmap,
             data,
+            validity: Validity::Valid,
         }
     }
 
+    /// Set the result of verifying the page header crc32
+    pub fn with_validity(mut self, validity: Validity) -> Page {
+        self.validity = validity;
+        self
+    }
+
     pub fn state(&self) -> &State {
         &self.state
     }
 
+    pub fn seq_no(&self) -> u32 {
+        self.seq_no
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn unused(&self) -> &[u8] {
+        &self.unused
+    }
+
+    pub fn crc32(&self) -> u32 {
+        self.crc32
+    }
+
     pub fn entry_state_bitmap(&self) -> &[EntryStateBitmap] {
         &self.entry_state_bitmap
     }
@@ -43,9 +69,31 @@ impl Page {
     pub fn data(&self) -> &[u8] {
         &self.data
     }
+
+    pub fn validity(&self) -> Validity {
+        self.validity
+    }
 }
 
-#[derive(Debug, Clone)]
+/// The page format version. Version 2 splits blobs into chunks that can span
+/// multiple pages where version 1 keeps each blob within a single page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Version {
+    V1,
+    V2,
+}
+
+impl Version {
+    /// The value of the version as stored in the page header
+    pub fn value(&self) -> u8 {
+        match self {
+            Self::V1 => 0xff,
+            Self::V2 => 0xfe,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum State {
     Empty,
     Active,
@@ -55,8 +103,7 @@ pub enum State {
 }
 
 impl TryFrom<u32> for State {
-    // TODO: use a real error type here
-    type Error = ();
+    type Error = InvalidStateError;
 
     fn try_from(val: u32) -> Result<Self, Self::Error> {
         match val {
@@ -65,7 +112,20 @@ impl TryFrom<u32> for State {
             0xfffffffc => Ok(Self::Full),
             0xfffffffe => Ok(Self::Active),
             0xffffffff => Ok(Self::Empty),
-            _ => Err(()),
+            _ => Err(InvalidStateError { value: val }),
+        }
+    }
+}
+
+impl State {
+    /// The value of the state as stored in the page header
+    pub fn value(&self) -> u32 {
+        match self {
+            Self::Corrupted => 0xfffffff0,
+            Self::Erasing => 0xfffffff8,
+            Self::Full => 0xfffffffc,
+            Self::Active => 0xfffffffe,
+            Self::Empty => 0xffffffff,
         }
     }
 }
@@ -75,6 +135,20 @@ pub enum EntryStateBitmap {
     Empty,
     Written,
     Erased,
+    /// The undefined state 0b01, the firmware treats the entry as corrupt
+    Illegal,
+}
+
+impl EntryStateBitmap {
+    /// The two bit value of the state as stored in the entry state bitmap
+    pub fn value(&self) -> u8 {
+        match self {
+            Self::Erased => 0,
+            Self::Illegal => 1,
+            Self::Written => 2,
+            Self::Empty => 3,
+        }
+    }
 }
 
 impl TryFrom<u8> for EntryStateBitmap {
@@ -83,15 +157,52 @@ impl TryFrom<u8> for EntryStateBitmap {
     fn try_from(val: u8) -> Result<Self, Self::Error> {
         match val {
             0 => Ok(Self::Erased),
+            1 => Ok(Self::Illegal),
             2 => Ok(Self::Written),
             3 => Ok(Self::Empty),
-            _ => Err(InvalidBitmapError { value: val }),
+            _ => Err(InvalidBitmapError::new(val)),
         }
     }
 }
 
-// TODO: implement std::error::Error
+#[derive(Debug, Clone)]
+pub struct InvalidStateError {
+    value: u32,
+}
+
+impl InvalidStateError {
+    pub fn value(&self) -> u32 {
+        self.value
+    }
+}
+
+impl std::fmt::Display for InvalidStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid page state {:#010x}", self.value)
+    }
+}
+
+impl std::error::Error for InvalidStateError {}
+
 #[derive(Debug, Clone)]
 pub struct InvalidBitmapError {
     value: u8,
 }
+
+impl InvalidBitmapError {
+    pub(crate) fn new(value: u8) -> InvalidBitmapError {
+        InvalidBitmapError { value }
+    }
+
+    pub fn value(&self) -> u8 {
+        self.value
+    }
+}
+
+impl std::fmt::Display for InvalidBitmapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid entry state {:#04b}", self.value)
+    }
+}
+
+impl std::error::Error for InvalidBitmapError {}
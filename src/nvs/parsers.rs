@@ -1,17 +1,29 @@
 use std::convert::TryFrom;
-use std::ffi::CString;
 
-use nom::combinator::map_res;
+use nom::bytes::complete::take;
+use nom::combinator::{map_res, peek};
 use nom::multi::count;
 use nom::number::complete::{le_i16, le_i32, le_i64, le_i8, le_u16, le_u32, le_u64, le_u8};
 use nom::sequence::tuple;
 use nom::IResult;
 
-use crate::nvs::event::{Entry, EntryType};
-use crate::nvs::page::{EntryStateBitmap, Page};
+use crate::nvs::error::ParseError;
 
-pub(crate) fn page(input: &[u8]) -> IResult<&[u8], Page> {
-    let (input, state) = map_res(le_u32, |val| crate::nvs::page::State::try_from(val))(input)?;
+use crate::nvs::crc;
+use crate::nvs::event::{Entry, EntryType, Validity};
+use crate::nvs::nvs::CrcPolicy;
+use crate::nvs::page::{EntryStateBitmap, Page, State};
+
+pub(crate) fn page(input: &[u8]) -> IResult<&[u8], Page, ParseError> {
+    let (_, state) = peek(map_res(le_u32, State::try_from))(input)?;
+    page_with_state(input, state)
+}
+
+/// Parse a page as being in `state` whatever state its header holds, this is how
+/// the firmware treats a page with an unknown state as corrupted
+pub(crate) fn page_with_state(input: &[u8], state: State) -> IResult<&[u8], Page, ParseError> {
+    let (_, header) = peek(take(32usize))(input)?;
+    let (input, _) = le_u32(input)?;
     let (input, seq_no) = le_u32(input)?;
     let (input, version) = le_u8(input)?;
     let (input, unused) = count(le_u8, 19)(input)?;
@@ -23,7 +35,8 @@ pub(crate) fn page(input: &[u8]) -> IResult<&[u8], Page> {
     for word in bitmaps_raw {
         for i in 0..16 {
             let val = ((word >> (i * 2)) & 0x3) as u8;
-            let entry = EntryStateBitmap::try_from(val).unwrap();
+            let entry = EntryStateBitmap::try_from(val)
+                .map_err(|err| nom::Err::Failure(ParseError::Bitmap(err)))?;
             entry_state_bitmap.push(entry);
         }
     }
@@ -37,30 +50,110 @@ pub(crate) fn page(input: &[u8]) -> IResult<&[u8], Page> {
     //    ))(input)?;
     let (input, data) = count(le_u8, 32 * 126)(input)?;
 
-    Ok((
-        input,
-        Page::new(
-            state,
-            seq_no,
-            version,
-            unused,
-            crc32,
-            entry_state_bitmap,
-            data,
-        ),
-    ))
+    // the crc covers the header from the sequence number up to the crc itself. An
+    // uninitialized page is all 0xff and has no crc to check.
+    let validity = match state {
+        State::Empty => Validity::Valid,
+        _ if crc::crc32(&header[4..28]) == crc32 => Validity::Valid,
+        _ => Validity::InvalidHeaderCrc,
+    };
+
+    let page = Page::new(
+        state,
+        seq_no,
+        version,
+        unused,
+        crc32,
+        entry_state_bitmap,
+        data,
+    );
+
+    Ok((input, page.with_validity(validity)))
 }
 
-pub(crate) fn entry(input: &[u8], page: u8, start: u8) -> IResult<&[u8], Entry> {
+/// Parse the entry at the start of `input`. Entries that can't be trusted are
+/// returned with a validity other than `Valid`, apart from an unknown type or a
+/// key that is not utf-8 which only fail parsing under `CrcPolicy::Error`.
+pub(crate) fn entry(
+    input: &[u8],
+    page: u8,
+    start: u8,
+    crc_policy: CrcPolicy,
+) -> IResult<&[u8], Entry, ParseError> {
+    let remaining = input.len();
+    let (_, header) = peek(take(32usize))(input)?;
     let (input, ns) = le_u8(input)?;
     let (input, entry_type) = le_u8(input)?;
     let (input, span) = le_u8(input)?;
     let (input, chunk_index) = le_u8(input)?;
     let (input, crc32) = le_u32(input)?;
     let (input, key_raw) = count(le_u8, 16)(input)?;
-    let first_null = key_raw.iter().position(|b| *b == 0).unwrap();
-    let str_bytes = key_raw[0..first_null].to_owned();
-    let key = CString::new(str_bytes).unwrap().into_string().unwrap();
+    let first_null = key_raw
+        .iter()
+        .position(|b| *b == 0)
+        .unwrap_or(key_raw.len());
+    let key_raw = &key_raw[0..first_null];
+    // a single corrupt entry in place of the one that failed verification
+    let corrupt = |key: String, validity: Validity| {
+        Entry::new(
+            ns,
+            span,
+            chunk_index,
+            crc32,
+            key,
+            EntryType::Any,
+            page,
+            start,
+            start + 1,
+        )
+        .with_validity(validity)
+    };
+
+    // the header crc covers everything in the entry except for the crc itself
+    if crc::crc32_update(crc::crc32(&header[0..4]), &header[8..32]) != crc32 {
+        // none of the header can be trusted, including the key, type and span, so
+        // the esp-idf treats this as a single corrupt entry and so do we
+        let (input, _) = take(8usize)(input)?;
+        let key = String::from_utf8_lossy(key_raw).into_owned();
+        return Ok((input, corrupt(key, Validity::InvalidHeaderCrc)));
+    }
+
+    let (key, valid_key) = match String::from_utf8(key_raw.to_owned()) {
+        Ok(key) => (key, true),
+        Err(_) if crc_policy == CrcPolicy::Error => {
+            return Err(nom::Err::Failure(ParseError::InvalidKey))
+        }
+        Err(_) => (String::from_utf8_lossy(key_raw).into_owned(), false),
+    };
+
+    // every entry must fit within what is left of the page
+    if span == 0 || span as usize * 32 > remaining {
+        let (input, _) = take(8usize)(input)?;
+        return Ok((input, corrupt(key, Validity::Malformed)));
+    }
+
+    // the header is intact so the whole span of an entry that can't be read is
+    // skipped, rather than reading its data as further entries
+    let skipped = |key: String| {
+        Entry::new(
+            ns,
+            span,
+            chunk_index,
+            crc32,
+            key,
+            EntryType::Any,
+            page,
+            start,
+            start + span,
+        )
+        .with_validity(Validity::Malformed)
+    };
+    if !valid_key {
+        let (input, _) = take(8usize)(input)?;
+        return Ok((input, skipped(key)));
+    }
+
+    let mut validity = Validity::Valid;
 
     let (input, data) = match entry_type {
         0x1 => {
@@ -101,35 +194,98 @@ pub(crate) fn entry(input: &[u8], page: u8, start: u8) -> IResult<&[u8], Entry>
             let (input, data) = le_i64(input)?;
             (input, EntryType::I64(data))
         }
-        0x21 => unimplemented!(),
+        0x21 => {
+            let (input, (size, _, crc32)) = tuple((le_u16, le_u16, le_u32))(input)?;
+            let (input, (data, data_validity)) = variable_length_data(input, span, size, crc32)?;
+            validity = data_validity;
+            // strings are stored with their null terminator included in the size
+            let end = data.iter().position(|b| *b == 0).unwrap_or(data.len());
+            let string = String::from_utf8_lossy(&data[0..end]).to_string();
+
+            (input, EntryType::String(string))
+        }
         0x41 => {
             // legacy style blobs where data is stored directly after
             let (input, (size, _, crc32)) = tuple((le_u16, le_u16, le_u32))(input)?;
-            let rounded_size = (size + 32 - 1) & !(32 - 1);
-            let (input, data) = count(le_u8, size as usize)(input)?;
-            let padding = rounded_size - size;
-            let (input, _) = count(le_u8, padding as usize)(input)?;
+            let (input, (data, data_validity)) = variable_length_data(input, span, size, crc32)?;
+            validity = data_validity;
 
             (input, EntryType::Blob(data))
         }
-        0x42 => unimplemented!(),
-        0x48 => unimplemented!(),
+        0x42 => {
+            // a single chunk of a multi-page blob, tied together by a blob index
+            let (input, (size, _, crc32)) = tuple((le_u16, le_u16, le_u32))(input)?;
+            let (input, (data, data_validity)) = variable_length_data(input, span, size, crc32)?;
+            validity = data_validity;
+
+            (input, EntryType::BlobData(data))
+        }
+        0x48 => {
+            let (input, (size, chunk_count, chunk_start, _)) =
+                tuple((le_u32, le_u8, le_u8, le_u16))(input)?;
+
+            (
+                input,
+                EntryType::BlobIndex {
+                    size,
+                    chunk_count,
+                    chunk_start,
+                },
+            )
+        }
         0xff => (input, EntryType::Any),
-        _ => unimplemented!(),
+        _ if crc_policy == CrcPolicy::Error => {
+            return Err(nom::Err::Failure(ParseError::UnknownEntryType(entry_type)))
+        }
+        // the firmware skips over items of a type it doesn't know
+        _ => {
+            let (input, _) = take(8usize)(input)?;
+            return Ok((input, skipped(key)));
+        }
     };
 
-    Ok((
-        input,
-        Entry::new(
-            ns,
-            span,
-            chunk_index,
-            crc32,
-            key,
-            data,
-            page,
-            start,
-            start + span,
-        ),
-    ))
+    if validity == Validity::Malformed {
+        return Ok((input, corrupt(key, validity)));
+    }
+
+    let entry = Entry::new(
+        ns,
+        span,
+        chunk_index,
+        crc32,
+        key,
+        data,
+        page,
+        start,
+        start + span,
+    );
+
+    Ok((input, entry.with_validity(validity)))
+}
+
+/// Read the data of a variable length entry (string or blob) which is stored in
+/// the `span - 1` entries directly following the header entry. The size and crc
+/// come from the header entry and the data is padded out to a full entry. A crc
+/// mismatch or a size that does not fit within the span does not fail parsing,
+/// it is reported through the returned validity.
+fn variable_length_data(
+    input: &[u8],
+    span: u8,
+    size: u16,
+    crc: u32,
+) -> IResult<&[u8], (Vec<u8>, Validity), ParseError> {
+    let available = (span as usize).saturating_sub(1) * 32;
+    if size as usize > available {
+        return Ok((input, (vec![], Validity::Malformed)));
+    }
+
+    let (remainder, raw) = take(available)(input)?;
+    let data = &raw[0..size as usize];
+    let validity = if crc::crc32(data) == crc {
+        Validity::Valid
+    } else {
+        Validity::InvalidDataCrc
+    };
+
+    Ok((remainder, (data.to_vec(), validity)))
 }
@@ -0,0 +1,432 @@
+use crate::nvs::crc;
+use crate::nvs::error::GenerateError;
+use crate::nvs::event::EntryType;
+use crate::nvs::nvs::{ENTRY_COUNT, PAGE_SIZE};
+use crate::nvs::page::{EntryStateBitmap, Page, State, Version};
+
+pub(crate) const ENTRY_SIZE: usize = 32;
+/// Keys are stored in 16 bytes which includes the null terminator
+pub(crate) const MAX_KEY_LEN: usize = 15;
+/// The chunk index of every entry that is not a chunk of a multi-page blob
+pub(crate) const CHUNK_ANY: u8 = 0xff;
+/// The most data a single string or blob entry can hold, which is every entry in
+/// a page other than its header entry
+pub(crate) const MAX_DATA_LEN: usize = (ENTRY_COUNT - 1) * ENTRY_SIZE;
+
+/// The largest blob a version 1 partition can hold, the firmware and
+/// nvs_partition_gen.py both limit these to half a page of entries less the
+/// header entry
+pub(crate) const MAX_V1_BLOB_LEN: usize = 1984;
+
+/// Version 2 blobs can be split into at most this many chunks
+const MAX_CHUNKS: usize = 127;
+
+pub(crate) type RawEntry = [u8; ENTRY_SIZE];
+
+/// A page of an nvs partition that entries are being written to
+#[derive(Debug, Clone)]
+pub(crate) struct PageWriter {
+    state: State,
+    seq_no: u32,
+    version: Version,
+    bitmap: Vec<EntryStateBitmap>,
+    entries: Vec<RawEntry>,
+    /// Whether the page has changed since it was created or read
+    dirty: bool,
+}
+
+impl PageWriter {
+    pub(crate) fn new(state: State, seq_no: u32, version: Version) -> PageWriter {
+        PageWriter {
+            state,
+            seq_no,
+            version,
+            bitmap: vec![EntryStateBitmap::Empty; ENTRY_COUNT],
+            entries: vec![[0xff; ENTRY_SIZE]; ENTRY_COUNT],
+            dirty: true,
+        }
+    }
+
+    /// Start from a page read from a partition so entries can be appended to it
+    pub(crate) fn from_page(page: &Page) -> PageWriter {
+        let mut entries = vec![[0xff; ENTRY_SIZE]; ENTRY_COUNT];
+        for (entry, raw) in entries.iter_mut().zip(page.data().chunks(ENTRY_SIZE)) {
+            entry.copy_from_slice(raw);
+        }
+
+        PageWriter {
+            state: page.state().clone(),
+            seq_no: page.seq_no(),
+            version: match page.version() {
+                0xff => Version::V1,
+                _ => Version::V2,
+            },
+            bitmap: page.entry_state_bitmap()[0..ENTRY_COUNT].to_vec(),
+            entries,
+            dirty: false,
+        }
+    }
+
+    pub(crate) fn state(&self) -> &State {
+        &self.state
+    }
+
+    pub(crate) fn seq_no(&self) -> u32 {
+        self.seq_no
+    }
+
+    pub(crate) fn version(&self) -> Version {
+        self.version
+    }
+
+    pub(crate) fn is_dirty(&self) -> bool {
+        self.dirty
+    }
+
+    pub(crate) fn set_state(&mut self, state: State) {
+        self.state = state;
+        self.dirty = true;
+    }
+
+    /// The index of the slot after the last one that has been written, entries
+    /// are only ever appended to a page
+    pub(crate) fn next_free(&self) -> usize {
+        self.bitmap
+            .iter()
+            .rposition(|state| *state != EntryStateBitmap::Empty)
+            .map_or(0, |idx| idx + 1)
+    }
+
+    /// The number of slots left for new entries
+    pub(crate) fn free(&self) -> usize {
+        ENTRY_COUNT - self.next_free()
+    }
+
+    /// Append the entries that make up a single item, returning the slot the item
+    /// starts at or `None` if there is not enough room left in the page
+    pub(crate) fn push(&mut self, entries: &[RawEntry]) -> Option<usize> {
+        let start = self.next_free();
+        if entries.len() > ENTRY_COUNT - start {
+            return None;
+        }
+
+        for (i, entry) in entries.iter().enumerate() {
+            self.entries[start + i] = *entry;
+            self.bitmap[start + i] = EntryStateBitmap::Written;
+        }
+        self.dirty = true;
+
+        Some(start)
+    }
+
+    /// Mark the slots `start..end` as erased, their data is left in place the
+    /// same as on flash
+    pub(crate) fn erase(&mut self, start: usize, end: usize) {
+        for state in &mut self.bitmap[start..end.min(ENTRY_COUNT)] {
+            *state = EntryStateBitmap::Erased;
+        }
+        self.dirty = true;
+    }
+
+    /// Serialize the page. Uninitialized pages are left entirely as 0xFF the same
+    /// as erased flash.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0xff; PAGE_SIZE];
+        if self.state == State::Empty {
+            return bytes;
+        }
+
+        bytes[0..4].copy_from_slice(&self.state.value().to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.seq_no.to_le_bytes());
+        bytes[8] = self.version.value();
+        let crc32 = crc::crc32(&bytes[4..28]);
+        bytes[28..32].copy_from_slice(&crc32.to_le_bytes());
+
+        // two bits per entry packed into little endian words, the last two slots
+        // of the bitmap are unused and stay empty
+        for (i, state) in self.bitmap.iter().enumerate() {
+            let byte = 32 + i / 4;
+            let shift = (i % 4) * 2;
+            bytes[byte] &= !(0x3 << shift);
+            bytes[byte] |= state.value() << shift;
+        }
+
+        for (i, entry) in self.entries.iter().enumerate() {
+            let start = (i + 2) * ENTRY_SIZE;
+            bytes[start..start + ENTRY_SIZE].copy_from_slice(entry);
+        }
+
+        bytes
+    }
+}
+
+/// Appends items to the pages of a partition the way the firmware does. Items
+/// are written to the active page until it is full, then the next empty page
+/// becomes active with the following sequence number. One empty page is always
+/// kept free as the firmware needs it to reclaim erased entries.
+#[derive(Debug, Clone)]
+pub(crate) struct Writer {
+    pages: Vec<PageWriter>,
+    active: Option<usize>,
+    version: Version,
+}
+
+impl Writer {
+    /// Write to `pages`, continuing with the active page if there is one and
+    /// otherwise activating the first empty page. New pages use `version`.
+    pub(crate) fn new(pages: Vec<PageWriter>, version: Version) -> Writer {
+        let active = pages
+            .iter()
+            .enumerate()
+            .filter(|(_, page)| *page.state() == State::Active)
+            .max_by_key(|(_, page)| page.seq_no())
+            .map(|(idx, _)| idx);
+
+        let mut writer = Writer {
+            pages,
+            active,
+            version,
+        };
+        if writer.active.is_none() {
+            // without a free page there is nothing to write to which is
+            // reported on the first write
+            let _ = writer.next_page();
+        }
+        writer
+    }
+
+    pub(crate) fn pages(&self) -> &[PageWriter] {
+        &self.pages
+    }
+
+    /// Mark the slots `start..end` of page `page` as erased
+    pub(crate) fn erase(&mut self, page: usize, start: usize, end: usize) {
+        if let Some(page) = self.pages.get_mut(page) {
+            page.erase(start, end);
+        }
+    }
+
+    /// Write an item, splitting blobs into chunks when using the version 2 format.
+    /// `chunk_start` is the first chunk index of a version 2 blob, the firmware
+    /// alternates between 0 and 128 each time a blob is rewritten.
+    pub(crate) fn write_item(
+        &mut self,
+        ns: u8,
+        key: &str,
+        value: &EntryType,
+        chunk_start: u8,
+    ) -> Result<(), GenerateError> {
+        let too_large = || GenerateError::ValueTooLarge {
+            key: key.to_owned(),
+        };
+        match value {
+            EntryType::Blob(data) if self.version == Version::V2 => {
+                self.write_blob(ns, key, data, chunk_start)
+            }
+            EntryType::String(data) if data.len() + 1 > MAX_DATA_LEN => Err(too_large()),
+            EntryType::Blob(data) if data.len() > MAX_V1_BLOB_LEN => Err(too_large()),
+            EntryType::BlobData(_) | EntryType::BlobIndex { .. } | EntryType::Any => {
+                Err(GenerateError::UnsupportedType {
+                    key: key.to_owned(),
+                })
+            }
+            value => self.write(&encode(ns, key, CHUNK_ANY, value)),
+        }
+    }
+
+    /// Write the entries of an item to the active page, moving on to a new page
+    /// when they don't fit as an item can not span pages
+    fn write(&mut self, entries: &[RawEntry]) -> Result<(), GenerateError> {
+        if self.active()?.push(entries).is_none() {
+            self.next_page()?;
+            self.active()?.push(entries);
+        }
+        Ok(())
+    }
+
+    /// Write a version 2 blob as chunks filling the remaining space of each page
+    /// followed by the blob index
+    fn write_blob(
+        &mut self,
+        ns: u8,
+        key: &str,
+        data: &[u8],
+        chunk_start: u8,
+    ) -> Result<(), GenerateError> {
+        let mut chunks = 0;
+        let mut remaining = data;
+        while !remaining.is_empty() {
+            // a chunk needs a header entry and at least one entry of data
+            if self.active()?.free() < 2 {
+                self.next_page()?;
+            }
+
+            if chunks == MAX_CHUNKS {
+                return Err(GenerateError::ValueTooLarge {
+                    key: key.to_owned(),
+                });
+            }
+
+            let len = remaining
+                .len()
+                .min((self.active()?.free() - 1) * ENTRY_SIZE);
+            let chunk = EntryType::BlobData(remaining[0..len].to_vec());
+            self.write(&encode(ns, key, chunk_start + chunks as u8, &chunk))?;
+            remaining = &remaining[len..];
+            chunks += 1;
+        }
+
+        let index = EntryType::BlobIndex {
+            size: data.len() as u32,
+            chunk_count: chunks as u8,
+            chunk_start,
+        };
+        self.write(&encode(ns, key, CHUNK_ANY, &index))
+    }
+
+    fn active(&mut self) -> Result<&mut PageWriter, GenerateError> {
+        match self.active {
+            Some(idx) => Ok(&mut self.pages[idx]),
+            None => Err(GenerateError::PartitionFull),
+        }
+    }
+
+    /// Mark the active page full and activate the next empty page after it
+    fn next_page(&mut self) -> Result<(), GenerateError> {
+        let empty = self
+            .pages
+            .iter()
+            .filter(|page| *page.state() == State::Empty)
+            .count();
+        if empty < 2 {
+            return Err(GenerateError::PartitionFull);
+        }
+
+        let seq_no = self
+            .pages
+            .iter()
+            .filter(|page| *page.state() != State::Empty)
+            .map(|page| page.seq_no() + 1)
+            .max()
+            .unwrap_or(0);
+        let start = self.active.map_or(0, |idx| idx + 1);
+        let count = self.pages.len();
+        let next = (0..count)
+            .map(|i| (start + i) % count)
+            .find(|idx| *self.pages[*idx].state() == State::Empty)
+            .ok_or(GenerateError::PartitionFull)?;
+
+        if let Some(idx) = self.active {
+            self.pages[idx].set_state(State::Full);
+        }
+        self.pages[next] = PageWriter::new(State::Active, seq_no, self.version);
+        self.active = Some(next);
+        Ok(())
+    }
+}
+
+/// The numeric type of an entry as stored in the entry header
+pub(crate) fn type_code(data: &EntryType) -> Option<u8> {
+    match data {
+        EntryType::U8(_) => Some(0x01),
+        EntryType::U16(_) => Some(0x02),
+        EntryType::U32(_) => Some(0x04),
+        EntryType::U64(_) => Some(0x08),
+        EntryType::I8(_) => Some(0x11),
+        EntryType::I16(_) => Some(0x12),
+        EntryType::I32(_) => Some(0x14),
+        EntryType::I64(_) => Some(0x18),
+        EntryType::String(_) => Some(0x21),
+        EntryType::Blob(_) => Some(0x41),
+        EntryType::BlobData(_) => Some(0x42),
+        EntryType::BlobIndex { .. } => Some(0x48),
+        EntryType::Any => None,
+    }
+}
+
+/// Encode an item as its header entry followed by the entries holding its data
+/// for strings and blobs. `Blob` is encoded as a version 1 blob, version 2 blobs
+/// are written as `BlobData` chunks and a `BlobIndex`. The key must be at most
+/// `MAX_KEY_LEN` bytes and variable length data at most `MAX_DATA_LEN` bytes.
+pub(crate) fn encode(ns: u8, key: &str, chunk_index: u8, data: &EntryType) -> Vec<RawEntry> {
+    let mut value = [0xff; 8];
+    let mut variable = None;
+    match data {
+        EntryType::U8(val) => value[0..1].copy_from_slice(&val.to_le_bytes()),
+        EntryType::U16(val) => value[0..2].copy_from_slice(&val.to_le_bytes()),
+        EntryType::U32(val) => value[0..4].copy_from_slice(&val.to_le_bytes()),
+        EntryType::U64(val) => value.copy_from_slice(&val.to_le_bytes()),
+        EntryType::I8(val) => value[0..1].copy_from_slice(&val.to_le_bytes()),
+        EntryType::I16(val) => value[0..2].copy_from_slice(&val.to_le_bytes()),
+        EntryType::I32(val) => value[0..4].copy_from_slice(&val.to_le_bytes()),
+        EntryType::I64(val) => value.copy_from_slice(&val.to_le_bytes()),
+        EntryType::String(val) => {
+            // strings are stored with their null terminator
+            let mut bytes = val.as_bytes().to_vec();
+            bytes.push(0);
+            variable = Some(bytes);
+        }
+        EntryType::Blob(val) | EntryType::BlobData(val) => variable = Some(val.clone()),
+        EntryType::BlobIndex {
+            size,
+            chunk_count,
+            chunk_start,
+        } => {
+            value[0..4].copy_from_slice(&size.to_le_bytes());
+            value[4] = *chunk_count;
+            value[5] = *chunk_start;
+        }
+        EntryType::Any => {}
+    }
+
+    let entry_type = type_code(data).unwrap_or(0xff);
+    let bytes = match variable {
+        Some(bytes) => bytes,
+        None => return vec![header(ns, entry_type, 1, chunk_index, key, value)],
+    };
+
+    value[0..2].copy_from_slice(&(bytes.len() as u16).to_le_bytes());
+    value[4..8].copy_from_slice(&crc::crc32(&bytes).to_le_bytes());
+    let data_entries = (bytes.len() + ENTRY_SIZE - 1) / ENTRY_SIZE;
+
+    let mut entries = Vec::with_capacity(data_entries + 1);
+    entries.push(header(
+        ns,
+        entry_type,
+        (data_entries + 1) as u8,
+        chunk_index,
+        key,
+        value,
+    ));
+    for chunk in bytes.chunks(ENTRY_SIZE) {
+        let mut entry = [0xff; ENTRY_SIZE];
+        entry[0..chunk.len()].copy_from_slice(chunk);
+        entries.push(entry);
+    }
+
+    entries
+}
+
+fn header(
+    ns: u8,
+    entry_type: u8,
+    span: u8,
+    chunk_index: u8,
+    key: &str,
+    value: [u8; 8],
+) -> RawEntry {
+    let mut entry = [0; ENTRY_SIZE];
+    entry[0] = ns;
+    entry[1] = entry_type;
+    entry[2] = span;
+    entry[3] = chunk_index;
+    let key = key.as_bytes();
+    let len = key.len().min(MAX_KEY_LEN);
+    entry[8..8 + len].copy_from_slice(&key[0..len]);
+    entry[24..32].copy_from_slice(&value);
+
+    // the crc covers everything in the entry except for the crc itself
+    let crc32 = crc::crc32_update(crc::crc32(&entry[0..4]), &entry[8..32]);
+    entry[4..8].copy_from_slice(&crc32.to_le_bytes());
+    entry
+}
@@ -0,0 +1,287 @@
+use crate::partition_table::{
+    CsvError, CsvErrorKind, NameRegistry, Partition, PartitionType, Subtype, APP_ALIGNMENT,
+    DATA_ALIGNMENT, FLAG_ENCRYPTED, FLAG_READONLY, TABLE_REGION_SIZE,
+};
+
+const FIELDS: [&str; 6] = ["name", "type", "subtype", "offset", "size", "flags"];
+
+/// Parse a partitions.csv the way gen_esp32part.py does. `table_offset` is where
+/// the partition table will be flashed and is used to place partitions that
+/// leave their offset empty. Custom types and subtypes are looked up in `names`.
+pub(crate) fn parse(
+    input: &str,
+    table_offset: u32,
+    names: &NameRegistry,
+) -> Result<Vec<Partition>, CsvError> {
+    let mut partitions = vec![];
+    // partitions without an offset are placed starting directly after the table
+    let mut next_offset = table_offset.saturating_add(TABLE_REGION_SIZE);
+
+    for (i, line) in input.lines().enumerate() {
+        let line_no = i + 1;
+        let trimmed = line.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+
+        let fields = split_fields(line);
+        if fields.len() > FIELDS.len() {
+            let (column, _) = fields[FIELDS.len()];
+            return Err(CsvError::new(line_no, column, CsvErrorKind::TooManyFields));
+        }
+
+        let field = |idx: usize| -> Result<(usize, &str), CsvError> {
+            fields.get(idx).copied().ok_or_else(|| {
+                CsvError::new(
+                    line_no,
+                    line.len() + 1,
+                    CsvErrorKind::MissingField(FIELDS[idx]),
+                )
+            })
+        };
+
+        let (_, name) = field(0)?;
+
+        let (column, value) = field(1)?;
+        let partition_type = parse_type(names, value).ok_or_else(|| {
+            CsvError::new(line_no, column, CsvErrorKind::InvalidType(value.into()))
+        })?;
+
+        let (column, value) = field(2)?;
+        let subtype = parse_subtype(names, &partition_type, value).ok_or_else(|| {
+            CsvError::new(line_no, column, CsvErrorKind::InvalidSubtype(value.into()))
+        })?;
+
+        let (column, value) = field(3)?;
+        let offset = if value.is_empty() && partition_type == PartitionType::PartitionTable {
+            table_offset
+        } else if value.is_empty() && partition_type == PartitionType::Bootloader {
+            // the bootloader offset depends on the chip so it can't be defaulted
+            return Err(CsvError::new(line_no, column, CsvErrorKind::MissingOffset));
+        } else if value.is_empty() {
+            let alignment = match partition_type {
+                PartitionType::App => APP_ALIGNMENT,
+                _ => DATA_ALIGNMENT,
+            };
+            align(next_offset, alignment).ok_or_else(|| {
+                CsvError::new(line_no, column, CsvErrorKind::InvalidNumber(value.into()))
+            })?
+        } else {
+            parse_int(value).ok_or_else(|| {
+                CsvError::new(line_no, column, CsvErrorKind::InvalidNumber(value.into()))
+            })?
+        };
+
+        let (column, value) = field(4)?;
+        let size = parse_int(value).ok_or_else(|| {
+            CsvError::new(line_no, column, CsvErrorKind::InvalidNumber(value.into()))
+        })?;
+
+        let flags = match fields.get(5) {
+            Some((column, value)) => parse_flags(value).map_err(|flag| {
+                CsvError::new(line_no, *column, CsvErrorKind::InvalidFlag(flag.into()))
+            })?,
+            None => 0,
+        };
+
+        // the bootloader and table regions come before the partitions so they
+        // don't move where the next partition is placed
+        if !matches!(
+            partition_type,
+            PartitionType::Bootloader | PartitionType::PartitionTable
+        ) {
+            next_offset = offset.saturating_add(size);
+        }
+        partitions.push(Partition::new(
+            name.to_owned(),
+            partition_type,
+            subtype,
+            offset,
+            size,
+            flags,
+        ));
+    }
+
+    Ok(partitions)
+}
+
+/// Render partitions as a partitions.csv in the same layout gen_esp32part.py
+/// produces. Types and subtypes without a built in or registered name are written
+/// as hex values.
+pub(crate) fn write(partitions: &[Partition], names: &NameRegistry) -> String {
+    let mut csv =
+        String::from("# ESP-IDF Partition Table\n# Name, Type, SubType, Offset, Size, Flags\n");
+    for partition in partitions {
+        let partition_type = &partition.partition_type;
+        let subtype = &partition.subtype;
+        let type_name = match names.type_name(partition_type) {
+            Some(name) => name.to_owned(),
+            None => format!("{:#x}", partition_type.value()),
+        };
+        let subtype_name = match names.subtype_name(partition_type, subtype) {
+            Some(name) => name.to_owned(),
+            None => format!("{:#x}", subtype.value()),
+        };
+
+        csv.push_str(&format!(
+            "{},{},{},{:#x},{},{}\n",
+            partition.name,
+            type_name,
+            subtype_name,
+            partition.offset,
+            format_size(partition.size),
+            format_flags(partition.flags),
+        ));
+    }
+
+    csv
+}
+
+/// Sizes are written with a M or K suffix when they are an exact multiple
+fn format_size(size: u32) -> String {
+    if size != 0 && size % (1024 * 1024) == 0 {
+        format!("{}M", size / (1024 * 1024))
+    } else if size != 0 && size % 1024 == 0 {
+        format!("{}K", size / 1024)
+    } else {
+        format!("{:#x}", size)
+    }
+}
+
+/// Only flags known to gen_esp32part.py can be represented, other bits are dropped
+fn format_flags(flags: u32) -> String {
+    let mut names = vec![];
+    if flags & FLAG_ENCRYPTED != 0 {
+        names.push("encrypted");
+    }
+    if flags & FLAG_READONLY != 0 {
+        names.push("readonly");
+    }
+
+    names.join(":")
+}
+
+/// Split a line into trimmed fields along with the 1 based column each starts at
+pub(crate) fn split_fields(line: &str) -> Vec<(usize, &str)> {
+    let mut fields = vec![];
+    let mut start = 0;
+    for field in line.split(',') {
+        let leading = field.len() - field.trim_start().len();
+        fields.push((start + leading + 1, field.trim()));
+        start += field.len() + 1;
+    }
+
+    fields
+}
+
+fn parse_type(names: &NameRegistry, value: &str) -> Option<PartitionType> {
+    names.find_type(value).or_else(|| {
+        let value = parse_int(value)?;
+        if value > 0xfe {
+            return None;
+        }
+
+        Some(PartitionType::from(value as u8))
+    })
+}
+
+fn parse_subtype(
+    names: &NameRegistry,
+    partition_type: &PartitionType,
+    value: &str,
+) -> Option<Subtype> {
+    // gen_esp32part.py defaults an empty subtype to the data `undefined` subtype
+    // 0x06 whatever the type, apps must name theirs
+    if value.is_empty() {
+        return match partition_type {
+            PartitionType::App => None,
+            _ => Some(Subtype::new(partition_type, 0x06)),
+        };
+    }
+
+    names.find_subtype(partition_type, value).or_else(|| {
+        let value = parse_int(value)?;
+        if value > 0xfe {
+            return None;
+        }
+
+        Some(Subtype::new(partition_type, value as u8))
+    })
+}
+
+/// Parse a number in any of the formats gen_esp32part.py accepts. This is hex with
+/// a `0x` prefix or decimal, either of which can be followed by a K or M suffix.
+pub fn parse_int(value: &str) -> Option<u32> {
+    let value = value.to_lowercase();
+    let (value, multiplier) = if let Some(value) = value.strip_suffix('k') {
+        (value, 1024)
+    } else if let Some(value) = value.strip_suffix('m') {
+        (value, 1024 * 1024)
+    } else {
+        (value.as_str(), 1)
+    };
+
+    let value = match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+        None => value.parse::<u32>().ok()?,
+    };
+
+    value.checked_mul(multiplier)
+}
+
+/// Flags are separated by colons, unknown flags are returned as the error
+fn parse_flags(value: &str) -> Result<u32, &str> {
+    let mut flags = 0;
+    for flag in value.split(':').map(|flag| flag.trim()) {
+        match flag {
+            "" => {}
+            "encrypted" => flags |= FLAG_ENCRYPTED,
+            "readonly" => flags |= FLAG_READONLY,
+            _ => return Err(flag),
+        }
+    }
+
+    Ok(flags)
+}
+
+fn align(value: u32, alignment: u32) -> Option<u32> {
+    match value % alignment {
+        0 => Some(value),
+        rem => value.checked_add(alignment - rem),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::partition_table::{PartitionTable, PartitionType, Subtype};
+
+    const CSV: &str = "# ESP-IDF Partition Table
+# Name, Type, SubType, Offset, Size, Flags
+nvs,data,nvs,0x9000,24K,
+otadata,data,ota,0xf000,8K,
+phy_init,data,phy,0x11000,4K,readonly
+factory,app,factory,0x20000,1M,
+ota_0,app,ota_0,0x120000,1M,encrypted
+custom,0x40,0x1,0x220000,0x1880,
+";
+
+    #[test]
+    fn csv_round_trip() {
+        let table = PartitionTable::from_csv(CSV).unwrap();
+        assert_eq!(table.to_csv(), CSV);
+
+        let bytes = table.to_bytes();
+        let parsed = PartitionTable::parse(&bytes).unwrap();
+        assert_eq!(parsed.to_csv(), CSV);
+    }
+
+    #[test]
+    fn empty_subtype_is_undefined() {
+        let table = PartitionTable::from_csv("storage,data,,0x9000,4K\n").unwrap();
+        let partition = &table.partitions()[0];
+        assert_eq!(partition.partition_type(), &PartitionType::Data);
+        assert_eq!(partition.subtype(), &Subtype::DataUndefined);
+
+        assert!(PartitionTable::from_csv("factory,app,,0x10000,1M\n").is_err());
+    }
+}
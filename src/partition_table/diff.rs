@@ -0,0 +1,410 @@
+use std::fmt::Formatter;
+
+use crate::partition_table::{Partition, PartitionTable, PartitionType, Severity, Subtype};
+
+/// A difference to a single partition between two tables. Partitions are matched
+/// up by name so a renamed partition is reported as removed and added.
+#[derive(Debug, Clone)]
+pub enum Change {
+    Added(Partition),
+    Removed(Partition),
+    Moved {
+        name: String,
+        from: u32,
+        to: u32,
+    },
+    Resized {
+        name: String,
+        from: u32,
+        to: u32,
+    },
+    /// The type or subtype changed
+    Retyped {
+        name: String,
+        from: (PartitionType, Subtype),
+        to: (PartitionType, Subtype),
+    },
+    FlagsChanged {
+        name: String,
+        from: u32,
+        to: u32,
+    },
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Added(partition) => write!(
+                f,
+                "added {} at {:#x} with size {:#x}",
+                partition.name, partition.offset, partition.size
+            ),
+            Self::Removed(partition) => write!(
+                f,
+                "removed {} at {:#x} with size {:#x}",
+                partition.name, partition.offset, partition.size
+            ),
+            Self::Moved { name, from, to } => {
+                write!(f, "moved {} from {:#x} to {:#x}", name, from, to)
+            }
+            Self::Resized { name, from, to } => {
+                write!(f, "resized {} from {:#x} to {:#x}", name, from, to)
+            }
+            Self::Retyped { name, from, to } => write!(
+                f,
+                "changed {} from type {:#x} subtype {:#x} to type {:#x} subtype {:#x}",
+                name,
+                from.0.value(),
+                from.1.value(),
+                to.0.value(),
+                to.1.value()
+            ),
+            Self::FlagsChanged { name, from, to } => {
+                write!(f, "changed {} flags from {:#x} to {:#x}", name, from, to)
+            }
+        }
+    }
+}
+
+/// A problem moving a device from one table to another with an OTA update
+#[derive(Debug, Clone, PartialEq)]
+pub struct MigrationIssue {
+    severity: Severity,
+    kind: MigrationIssueKind,
+}
+
+impl MigrationIssue {
+    pub fn new(severity: Severity, kind: MigrationIssueKind) -> MigrationIssue {
+        MigrationIssue { severity, kind }
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn kind(&self) -> &MigrationIssueKind {
+        &self.kind
+    }
+}
+
+impl std::fmt::Display for MigrationIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.severity, self.kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MigrationIssueKind {
+    /// The NVS partition is gone, moved, shrank or changed subtype so the data
+    /// stored in it is lost
+    NvsLost { name: String },
+    /// The nvs_keys partition changed so encrypted NVS can no longer be read
+    NvsKeysLost { name: String },
+    /// The bootloader selects the app to boot from otadata, if it changes the
+    /// device falls back to the factory app or fails to boot
+    OtaDataChanged { name: String },
+    /// The app the device is running may be in a slot that is no longer there
+    AppMoved { name: String },
+    /// The app slot is smaller than the firmware that will be written to it
+    AppTooSmall {
+        name: String,
+        size: u32,
+        firmware_size: u32,
+    },
+    /// The contents of the data partition are lost unless they are migrated
+    DataMoved { name: String },
+}
+
+impl std::fmt::Display for MigrationIssueKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NvsLost { name } => write!(f, "data in nvs partition {} will be lost", name),
+            Self::NvsKeysLost { name } => write!(
+                f,
+                "keys in nvs_keys partition {} will be lost, encrypted nvs can not be read",
+                name
+            ),
+            Self::OtaDataChanged { name } => write!(
+                f,
+                "otadata partition {} changed, the boot app selection will be lost",
+                name
+            ),
+            Self::AppMoved { name } => write!(
+                f,
+                "app partition {} changed, the running app may not boot",
+                name
+            ),
+            Self::AppTooSmall {
+                name,
+                size,
+                firmware_size,
+            } => write!(
+                f,
+                "app partition {} of {:#x} bytes is smaller than the {:#x} byte firmware",
+                name, size, firmware_size
+            ),
+            Self::DataMoved { name } => write!(
+                f,
+                "contents of partition {} will be lost unless migrated",
+                name
+            ),
+        }
+    }
+}
+
+pub(crate) fn diff(old: &PartitionTable, new: &PartitionTable) -> Vec<Change> {
+    let mut changes = vec![];
+
+    for before in &old.partitions {
+        let after = match new.find_by_name(&before.name) {
+            Some(after) => after,
+            None => {
+                changes.push(Change::Removed(before.clone()));
+                continue;
+            }
+        };
+
+        let name = before.name.clone();
+        if before.offset != after.offset {
+            changes.push(Change::Moved {
+                name: name.clone(),
+                from: before.offset,
+                to: after.offset,
+            });
+        }
+
+        if before.size != after.size {
+            changes.push(Change::Resized {
+                name: name.clone(),
+                from: before.size,
+                to: after.size,
+            });
+        }
+
+        if before.partition_type != after.partition_type || before.subtype != after.subtype {
+            changes.push(Change::Retyped {
+                name: name.clone(),
+                from: (before.partition_type.clone(), before.subtype.clone()),
+                to: (after.partition_type.clone(), after.subtype.clone()),
+            });
+        }
+
+        if before.flags != after.flags {
+            changes.push(Change::FlagsChanged {
+                name,
+                from: before.flags,
+                to: after.flags,
+            });
+        }
+    }
+
+    for after in &new.partitions {
+        if old.find_by_name(&after.name).is_none() {
+            changes.push(Change::Added(after.clone()));
+        }
+    }
+
+    changes
+}
+
+/// Check whether a device using `old` can be updated to `new` over OTA without
+/// losing data or being unable to boot. `firmware_size` is the size of the app
+/// that will be written to the app slots if it is known.
+pub(crate) fn check_migration(
+    old: &PartitionTable,
+    new: &PartitionTable,
+    firmware_size: Option<u32>,
+) -> Vec<MigrationIssue> {
+    let mut issues = vec![];
+    let mut error = |kind| issues.push(MigrationIssue::new(Severity::Error, kind));
+
+    for before in &old.partitions {
+        let after = new.find_by_name(&before.name);
+        let name = before.name.clone();
+        let same_place = after.map_or(false, |after| {
+            after.offset == before.offset
+                && after.partition_type == before.partition_type
+                && after.subtype == before.subtype
+        });
+        let unchanged = same_place && after.map_or(false, |after| after.size == before.size);
+
+        match before.subtype {
+            // NVS can grow in place, the new pages are treated as empty
+            Subtype::DataNvs
+                if !same_place || after.map_or(false, |after| after.size < before.size) =>
+            {
+                error(MigrationIssueKind::NvsLost { name })
+            }
+            Subtype::DataNvsKeys if !unchanged => error(MigrationIssueKind::NvsKeysLost { name }),
+            Subtype::DataOta if !unchanged => error(MigrationIssueKind::OtaDataChanged { name }),
+            _ if before.partition_type == PartitionType::App && !same_place => {
+                error(MigrationIssueKind::AppMoved { name })
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(firmware_size) = firmware_size {
+        for partition in &new.partitions {
+            if partition.partition_type == PartitionType::App && partition.size < firmware_size {
+                error(MigrationIssueKind::AppTooSmall {
+                    name: partition.name.clone(),
+                    size: partition.size,
+                    firmware_size,
+                });
+            }
+        }
+    }
+
+    let mut warning = |kind| issues.push(MigrationIssue::new(Severity::Warning, kind));
+
+    for before in &old.partitions {
+        if before.partition_type != PartitionType::Data
+            || matches!(
+                before.subtype,
+                Subtype::DataNvs | Subtype::DataNvsKeys | Subtype::DataOta
+            )
+        {
+            continue;
+        }
+
+        let unchanged = new.find_by_name(&before.name).map_or(false, |after| {
+            after.offset == before.offset
+                && after.size == before.size
+                && after.subtype == before.subtype
+        });
+        if !unchanged {
+            warning(MigrationIssueKind::DataMoved {
+                name: before.name.clone(),
+            });
+        }
+    }
+
+    issues
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = "nvs,data,nvs,0x9000,0x6000\n\
+                       otadata,data,ota,0xf000,0x2000\n\
+                       phy,data,phy,0x11000,0x1000\n\
+                       ota_0,app,ota_0,0x20000,1M\n\
+                       ota_1,app,ota_1,0x120000,1M\n\
+                       storage,data,spiffs,0x220000,64K\n\
+                       keys,data,nvs_keys,0x230000,4K\n";
+
+    const NEW: &str = "nvs,data,nvs,0x9000,0x6000\n\
+                       otadata,data,ota,0xf000,0x2000\n\
+                       ota_0,app,ota_0,0x20000,0x180000\n\
+                       ota_1,app,ota_1,0x1a0000,0x180000\n\
+                       storage,data,fat,0x320000,64K\n\
+                       keys,data,nvs_keys,0x330000,4K,encrypted\n\
+                       coredump,data,coredump,0x340000,64K\n";
+
+    fn csv(input: &str) -> PartitionTable {
+        PartitionTable::from_csv(input).unwrap()
+    }
+
+    #[test]
+    fn changes_between_tables() {
+        let changes: Vec<String> = csv(OLD)
+            .diff(&csv(NEW))
+            .iter()
+            .map(|change| change.to_string())
+            .collect();
+        assert_eq!(
+            changes,
+            [
+                "removed phy at 0x11000 with size 0x1000",
+                "resized ota_0 from 0x100000 to 0x180000",
+                "moved ota_1 from 0x120000 to 0x1a0000",
+                "resized ota_1 from 0x100000 to 0x180000",
+                "moved storage from 0x220000 to 0x320000",
+                "changed storage from type 0x1 subtype 0x82 to type 0x1 subtype 0x81",
+                "moved keys from 0x230000 to 0x330000",
+                "changed keys flags from 0x0 to 0x1",
+                "added coredump at 0x340000 with size 0x10000",
+            ]
+        );
+        assert!(csv(NEW).diff(&csv(NEW)).is_empty());
+    }
+
+    #[test]
+    fn migration_issues() {
+        let issues = csv(OLD).check_migration(&csv(NEW), Some(0x190000));
+        let kinds: Vec<(Severity, &MigrationIssueKind)> = issues
+            .iter()
+            .map(|issue| (issue.severity(), issue.kind()))
+            .collect();
+        let name = |name: &str| name.to_owned();
+        assert_eq!(
+            kinds,
+            [
+                (
+                    Severity::Error,
+                    &MigrationIssueKind::AppMoved {
+                        name: name("ota_1")
+                    }
+                ),
+                (
+                    Severity::Error,
+                    &MigrationIssueKind::NvsKeysLost { name: name("keys") }
+                ),
+                (
+                    Severity::Error,
+                    &MigrationIssueKind::AppTooSmall {
+                        name: name("ota_0"),
+                        size: 0x180000,
+                        firmware_size: 0x190000
+                    }
+                ),
+                (
+                    Severity::Error,
+                    &MigrationIssueKind::AppTooSmall {
+                        name: name("ota_1"),
+                        size: 0x180000,
+                        firmware_size: 0x190000
+                    }
+                ),
+                (
+                    Severity::Warning,
+                    &MigrationIssueKind::DataMoved { name: name("phy") }
+                ),
+                (
+                    Severity::Warning,
+                    &MigrationIssueKind::DataMoved {
+                        name: name("storage")
+                    }
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn nvs_and_otadata_changes() {
+        let resized = |size: &str| OLD.replacen("0x9000,0x6000", &format!("0x9000,{}", size), 1);
+        let nvs_issues = |new: &str| -> Vec<MigrationIssueKind> {
+            csv(OLD)
+                .check_migration(&csv(new), None)
+                .into_iter()
+                .map(|issue| issue.kind().clone())
+                .collect()
+        };
+
+        assert!(nvs_issues(&resized("0x6000")).is_empty());
+        assert_eq!(
+            nvs_issues(&resized("0x5000")),
+            [MigrationIssueKind::NvsLost {
+                name: "nvs".to_owned()
+            }]
+        );
+        assert_eq!(
+            nvs_issues(&OLD.replacen("otadata,data,ota,0xf000", "otadata,data,ota,0xe000", 1)),
+            [MigrationIssueKind::OtaDataChanged {
+                name: "otadata".to_owned()
+            }]
+        );
+    }
+}
@@ -0,0 +1,185 @@
+use std::fmt::Formatter;
+
+/// Errors that can occur while parsing a binary partition table. `index` is the
+/// zero based entry in the table and `offset` is the byte offset of that entry
+/// from the start of the table.
+#[derive(Debug)]
+pub enum PartitionTableError {
+    Io(std::io::Error),
+    /// The entry does not start with the 0xAA50 partition magic
+    MissingMagic {
+        index: usize,
+        offset: usize,
+    },
+    /// The data ends part way through an entry
+    TruncatedEntry {
+        index: usize,
+        offset: usize,
+    },
+    /// The data ends without either the 0xEBEB MD5 record or the 0xFF entry that
+    /// terminates tables without one
+    MissingMd5 {
+        offset: usize,
+    },
+    /// The table has more entries than fit in the partition table region
+    TooManyEntries {
+        offset: usize,
+    },
+}
+
+impl std::fmt::Display for PartitionTableError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "unable to read partition table: {}", err),
+            Self::MissingMagic { index, offset } => write!(
+                f,
+                "entry {} at offset {:#x} is missing the partition magic",
+                index, offset
+            ),
+            Self::TruncatedEntry { index, offset } => {
+                write!(f, "entry {} at offset {:#x} is truncated", index, offset)
+            }
+            Self::MissingMd5 { offset } => {
+                write!(f, "missing md5 record at offset {:#x}", offset)
+            }
+            Self::TooManyEntries { offset } => write!(
+                f,
+                "table continues past the maximum number of entries at offset {:#x}",
+                offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PartitionTableError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PartitionTableError {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+/// Error from parsing a partitions.csv. `line` and `column` are 1 based and
+/// point at the field that could not be parsed.
+#[derive(Debug, Clone)]
+pub struct CsvError {
+    line: usize,
+    column: usize,
+    kind: CsvErrorKind,
+}
+
+impl CsvError {
+    pub fn new(line: usize, column: usize, kind: CsvErrorKind) -> CsvError {
+        CsvError { line, column, kind }
+    }
+
+    pub fn line(&self) -> usize {
+        self.line
+    }
+
+    pub fn column(&self) -> usize {
+        self.column
+    }
+
+    pub fn kind(&self) -> &CsvErrorKind {
+        &self.kind
+    }
+}
+
+impl std::fmt::Display for CsvError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.kind
+        )
+    }
+}
+
+impl std::error::Error for CsvError {}
+
+#[derive(Debug, Clone)]
+pub enum CsvErrorKind {
+    /// The line ends before the named field
+    MissingField(&'static str),
+    /// There are more than the six name, type, subtype, offset, size and flags fields
+    TooManyFields,
+    InvalidType(String),
+    InvalidSubtype(String),
+    InvalidNumber(String),
+    InvalidFlag(String),
+    /// Bootloader partitions must be given an offset
+    MissingOffset,
+    /// A name registry line that is not a type or subtype
+    InvalidEntry(String),
+}
+
+impl std::fmt::Display for CsvErrorKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingField(field) => write!(f, "missing {} field", field),
+            Self::TooManyFields => write!(f, "too many fields"),
+            Self::InvalidType(value) => write!(f, "invalid type '{}'", value),
+            Self::InvalidSubtype(value) => write!(f, "invalid subtype '{}'", value),
+            Self::InvalidNumber(value) => write!(f, "invalid number '{}'", value),
+            Self::InvalidFlag(value) => write!(f, "invalid flag '{}'", value),
+            Self::MissingOffset => write!(f, "missing offset"),
+            Self::InvalidEntry(value) => {
+                write!(f, "invalid entry '{}', expected type or subtype", value)
+            }
+        }
+    }
+}
+
+/// Errors from planning a partition layout
+#[derive(Debug, Clone)]
+pub enum PlanError {
+    /// More than one partition was asked to fill the remaining space
+    MultipleFill,
+    /// There is not enough flash left to place the partition
+    DoesNotFit { name: String },
+    /// The offset a partition is pinned at does not meet its alignment
+    MisalignedPin { name: String, offset: u32 },
+    /// A pinned partition overlaps the bootloader, the partition table or
+    /// another pinned partition
+    PinnedOverlap { name: String },
+    /// There is no partition with the name given to grow
+    UnknownPartition { name: String },
+    /// A pinned partition comes after the partition being grown so it would have
+    /// to move to make room
+    PinnedInTheWay { name: String, pinned: String },
+}
+
+impl std::fmt::Display for PlanError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MultipleFill => write!(f, "only one partition can fill the remaining space"),
+            Self::DoesNotFit { name } => write!(f, "not enough space for partition {}", name),
+            Self::MisalignedPin { name, offset } => write!(
+                f,
+                "partition {} is pinned at {:#x} which is not aligned",
+                name, offset
+            ),
+            Self::PinnedOverlap { name } => write!(
+                f,
+                "pinned partition {} overlaps the partition table or another partition",
+                name
+            ),
+            Self::UnknownPartition { name } => write!(f, "no partition named {}", name),
+            Self::PinnedInTheWay { name, pinned } => write!(
+                f,
+                "partition {} can't grow past pinned partition {}",
+                name, pinned
+            ),
+        }
+    }
+}
+
+impl std::error::Error for PlanError {}
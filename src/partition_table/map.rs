@@ -0,0 +1,491 @@
+use std::fmt::Write;
+
+use crate::partition_table::{
+    NameRegistry, Partition, PartitionTable, PartitionType, TABLE_REGION_SIZE,
+};
+
+/// Width of the usage bar in the text map
+const BAR_WIDTH: usize = 20;
+const SVG_WIDTH: u32 = 640;
+/// Regions are scaled to their size but never drawn smaller than this so small
+/// partitions such as otadata are still readable
+const SVG_MIN_HEIGHT: f64 = 24.0;
+const SVG_SCALE_HEIGHT: f64 = 720.0;
+
+#[derive(Debug, Clone)]
+pub enum RegionKind {
+    /// Everything before the partition table, this is where the bootloader lives
+    Bootloader,
+    PartitionTable,
+    Partition(Partition),
+    /// Space not used by any partition
+    Free,
+}
+
+/// A contiguous range of the flash in a memory map
+#[derive(Debug, Clone)]
+pub struct Region {
+    offset: u32,
+    size: u32,
+    kind: RegionKind,
+    used: Option<u32>,
+}
+
+impl Region {
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    pub fn kind(&self) -> &RegionKind {
+        &self.kind
+    }
+
+    /// The number of bytes of the partition taken up by its payload, if known
+    pub fn used(&self) -> Option<u32> {
+        self.used
+    }
+}
+
+/// The layout of the flash described by a partition table, including the
+/// reserved regions before it and any free space between partitions
+#[derive(Debug, Clone)]
+pub struct MemoryMap {
+    regions: Vec<Region>,
+    names: NameRegistry,
+}
+
+impl MemoryMap {
+    /// Map a table flashed at `table_offset`. The map ends at `flash_size` when it
+    /// is given, otherwise it ends with the last partition.
+    pub fn new(table: &PartitionTable, table_offset: u32, flash_size: Option<u32>) -> MemoryMap {
+        let mut regions = vec![
+            Region {
+                offset: 0,
+                size: table_offset,
+                kind: RegionKind::Bootloader,
+                used: None,
+            },
+            Region {
+                offset: table_offset,
+                size: TABLE_REGION_SIZE,
+                kind: RegionKind::PartitionTable,
+                used: None,
+            },
+        ];
+
+        let mut partitions: Vec<&Partition> = table.partitions().iter().collect();
+        partitions.sort_by_key(|partition| partition.offset());
+
+        let mut cursor = table_offset as u64 + TABLE_REGION_SIZE as u64;
+        for partition in partitions {
+            if partition.offset() as u64 > cursor {
+                regions.push(free(cursor, partition.offset() as u64));
+            }
+
+            regions.push(Region {
+                offset: partition.offset(),
+                size: partition.size(),
+                kind: RegionKind::Partition(partition.clone()),
+                used: None,
+            });
+            cursor = cursor.max(partition.end());
+        }
+
+        if let Some(flash_size) = flash_size {
+            if (flash_size as u64) > cursor {
+                regions.push(free(cursor, flash_size as u64));
+            }
+        }
+
+        MemoryMap {
+            regions,
+            names: NameRegistry::new(),
+        }
+    }
+
+    /// Use the names in `names` for custom types and subtypes
+    pub fn names(mut self, names: &NameRegistry) -> MemoryMap {
+        self.names = names.clone();
+        self
+    }
+
+    /// Record the size of the payload written to the partition named `name`, this
+    /// is shown as how much of the partition is used
+    pub fn usage(mut self, name: &str, used: u32) -> MemoryMap {
+        for region in self.regions.iter_mut() {
+            if matches!(&region.kind, RegionKind::Partition(partition) if partition.name() == name)
+            {
+                region.used = Some(used);
+            }
+        }
+        self
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    /// Render the map as a table with a bar showing the usage of each partition
+    /// that has a payload
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{:<10}  {:>10}  {:>6}  {:<16}  {:<20}  Usage\n",
+            "Offset", "End", "Size", "Name", "Type"
+        );
+
+        for region in &self.regions {
+            let line = format!(
+                "{:<10}  {:>10}  {:>6}  {:<16}  {:<20}  {}",
+                format!("{:#x}", region.offset),
+                format!("{:#x}", region.offset as u64 + region.size as u64),
+                format_size(region.size as u64),
+                self.label(region),
+                self.type_label(region),
+                usage_bar(region),
+            );
+            text.push_str(line.trim_end());
+            text.push('\n');
+        }
+
+        let (allocated, free) = self.totals();
+        let total = allocated + free;
+        let _ = writeln!(
+            text,
+            "\n{} of {} allocated ({}%), {} free",
+            format_size(allocated),
+            format_size(total),
+            percent(allocated, total),
+            format_size(free),
+        );
+
+        let (used, size) = self.usage_totals();
+        if size > 0 {
+            let _ = writeln!(
+                text,
+                "{} of {} used by payloads ({}%)",
+                format_size(used),
+                format_size(size),
+                percent(used, size),
+            );
+        }
+
+        text
+    }
+
+    /// Render the map as an SVG with the flash drawn from top to bottom and each
+    /// region scaled to its size
+    pub fn to_svg(&self) -> String {
+        let total: u64 = self
+            .regions
+            .iter()
+            .map(|region| region.size as u64)
+            .sum::<u64>()
+            .max(1);
+
+        let heights: Vec<f64> = self
+            .regions
+            .iter()
+            .map(|region| {
+                (region.size as f64 / total as f64 * SVG_SCALE_HEIGHT).max(SVG_MIN_HEIGHT)
+            })
+            .collect();
+        let height: f64 = heights.iter().sum::<f64>() + 20.0;
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{:.0}\" \
+             font-family=\"monospace\" font-size=\"12\">\n",
+            SVG_WIDTH, height
+        );
+
+        let x = 100.0;
+        let width = 360.0;
+        let mut y = 10.0;
+        for (region, h) in self.regions.iter().zip(heights) {
+            let (fill, used_fill) = colors(region);
+            let dash = match region.kind {
+                RegionKind::Free => " stroke-dasharray=\"4 2\"",
+                _ => "",
+            };
+            let _ = writeln!(
+                svg,
+                "  <rect x=\"{}\" y=\"{:.1}\" width=\"{}\" height=\"{:.1}\" fill=\"{}\" \
+                 stroke=\"#333\"{}/>",
+                x, y, width, h, fill, dash
+            );
+
+            if let Some(used) = region.used {
+                let fraction = (used as f64 / region.size.max(1) as f64).min(1.0);
+                let _ = writeln!(
+                    svg,
+                    "  <rect x=\"{}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"{}\"/>",
+                    x + 1.0,
+                    y + 1.0,
+                    (width - 2.0) * fraction,
+                    h - 2.0,
+                    used_fill
+                );
+            }
+
+            let text_y = y + h / 2.0 + 4.0;
+            let _ = writeln!(
+                svg,
+                "  <text x=\"{}\" y=\"{:.1}\" text-anchor=\"end\">{:#x}</text>",
+                x - 8.0,
+                text_y,
+                region.offset
+            );
+            let _ = writeln!(
+                svg,
+                "  <text x=\"{}\" y=\"{:.1}\">{}</text>",
+                x + 8.0,
+                text_y,
+                escape(&format!(
+                    "{} {}",
+                    self.label(region),
+                    self.type_label(region)
+                ))
+            );
+
+            let mut size = format_size(region.size as u64);
+            if let Some(used) = region.used {
+                size = format!("{} ({}%)", size, percent(used as u64, region.size as u64));
+            }
+            let _ = writeln!(
+                svg,
+                "  <text x=\"{}\" y=\"{:.1}\">{}</text>",
+                x + width + 8.0,
+                text_y,
+                size
+            );
+
+            y += h;
+        }
+
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    fn label(&self, region: &Region) -> String {
+        match &region.kind {
+            RegionKind::Bootloader => "<bootloader>".to_owned(),
+            RegionKind::PartitionTable => "<partition table>".to_owned(),
+            RegionKind::Partition(partition) => partition.name().to_owned(),
+            RegionKind::Free => "<free>".to_owned(),
+        }
+    }
+
+    fn type_label(&self, region: &Region) -> String {
+        match &region.kind {
+            RegionKind::Partition(partition) => {
+                let partition_type = partition.partition_type();
+                let type_name = match self.names.type_name(partition_type) {
+                    Some(name) => name.to_owned(),
+                    None => format!("{:#x}", partition_type.value()),
+                };
+                let subtype_name =
+                    match self.names.subtype_name(partition_type, partition.subtype()) {
+                        Some(name) => name.to_owned(),
+                        None => format!("{:#x}", partition.subtype().value()),
+                    };
+                format!("{}/{}", type_name, subtype_name)
+            }
+            _ => String::new(),
+        }
+    }
+
+    /// The bytes allocated to partitions and the bytes left free
+    fn totals(&self) -> (u64, u64) {
+        self.regions
+            .iter()
+            .fold((0, 0), |(allocated, free), region| match region.kind {
+                RegionKind::Free => (allocated, free + region.size as u64),
+                _ => (allocated + region.size as u64, free),
+            })
+    }
+
+    /// The bytes used by payloads and the size of the partitions they are in
+    fn usage_totals(&self) -> (u64, u64) {
+        self.regions
+            .iter()
+            .filter_map(|region| region.used.map(|used| (used as u64, region.size as u64)))
+            .fold((0, 0), |(used, size), (u, s)| (used + u, size + s))
+    }
+}
+
+fn free(start: u64, end: u64) -> Region {
+    Region {
+        offset: start as u32,
+        size: (end - start) as u32,
+        kind: RegionKind::Free,
+        used: None,
+    }
+}
+
+fn usage_bar(region: &Region) -> String {
+    match region.used {
+        Some(used) => {
+            let fraction = (used as f64 / region.size.max(1) as f64).min(1.0);
+            let filled = (fraction * BAR_WIDTH as f64).round() as usize;
+            format!(
+                "[{}{}] {:>3}%",
+                "#".repeat(filled),
+                "-".repeat(BAR_WIDTH - filled),
+                percent(used as u64, region.size as u64)
+            )
+        }
+        None => String::new(),
+    }
+}
+
+/// The fill color of the region and of the part used by its payload
+fn colors(region: &Region) -> (&'static str, &'static str) {
+    match &region.kind {
+        RegionKind::Bootloader | RegionKind::PartitionTable => ("#d0d0d0", "#a0a0a0"),
+        RegionKind::Free => ("#ffffff", "#ffffff"),
+        RegionKind::Partition(partition) => match partition.partition_type() {
+            PartitionType::App => ("#cfe2ff", "#6ea8fe"),
+            PartitionType::Data => ("#d1e7dd", "#75b798"),
+            _ => ("#ffe5d0", "#feb272"),
+        },
+    }
+}
+
+fn percent(value: u64, total: u64) -> u64 {
+    (value * 100).checked_div(total).unwrap_or(0)
+}
+
+/// Sizes are shown in M or K to one decimal place, which is dropped when the size
+/// is an exact multiple
+fn format_size(size: u64) -> String {
+    let (value, suffix) = if size >= 1024 * 1024 {
+        (size as f64 / (1024.0 * 1024.0), "M")
+    } else if size >= 1024 {
+        (size as f64 / 1024.0, "K")
+    } else {
+        return size.to_string();
+    };
+
+    let value = format!("{:.1}", value);
+    format!("{}{}", value.trim_end_matches(".0"), suffix)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::partition_table::Subtype;
+
+    fn map() -> MemoryMap {
+        let partitions = [
+            ("nvs", PartitionType::Data, Subtype::DataNvs, 0x9000, 0x6000),
+            (
+                "factory",
+                PartitionType::App,
+                Subtype::AppFactory,
+                0x10000,
+                0x100000,
+            ),
+            (
+                "fs",
+                PartitionType::Data,
+                Subtype::DataFat,
+                0x120000,
+                0x80000,
+            ),
+        ];
+        let table = PartitionTable::from_partitions(
+            partitions
+                .iter()
+                .map(|(name, partition_type, subtype, offset, size)| {
+                    Partition::new(
+                        name.to_string(),
+                        partition_type.clone(),
+                        subtype.clone(),
+                        *offset,
+                        *size,
+                        0,
+                    )
+                })
+                .collect(),
+        );
+        table.memory_map(Some(0x200000))
+    }
+
+    #[test]
+    fn regions_include_reserved_and_free_space() {
+        let regions: Vec<(String, u32, u32)> = map()
+            .regions()
+            .iter()
+            .map(|region| {
+                let kind = match region.kind() {
+                    RegionKind::Partition(partition) => partition.name().to_owned(),
+                    kind => format!("{:?}", kind),
+                };
+                (kind, region.offset(), region.size())
+            })
+            .collect();
+
+        let region = |kind: &str, offset, size| (kind.to_owned(), offset, size);
+        assert_eq!(
+            regions,
+            [
+                region("Bootloader", 0x0, 0x8000),
+                region("PartitionTable", 0x8000, 0x1000),
+                region("nvs", 0x9000, 0x6000),
+                region("Free", 0xf000, 0x1000),
+                region("factory", 0x10000, 0x100000),
+                region("Free", 0x110000, 0x10000),
+                region("fs", 0x120000, 0x80000),
+                region("Free", 0x1a0000, 0x60000),
+            ]
+        );
+    }
+
+    #[test]
+    fn text() {
+        let text = map().usage("factory", 0xc0000).to_text();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 12);
+        assert_eq!(lines[1], "0x0             0x8000     32K  <bootloader>");
+        assert_eq!(
+            lines[5],
+            "0x10000       0x110000      1M  factory           app/factory           \
+             [###############-----]  75%"
+        );
+        assert_eq!(lines[8], "0x1a0000      0x200000    384K  <free>");
+        assert_eq!(lines[10], "1.6M of 2M allocated (77%), 452K free");
+        assert_eq!(lines[11], "768K of 1M used by payloads (75%)");
+
+        // the payload summary is left out when no usage is known
+        assert!(map().to_text().ends_with("452K free\n"));
+    }
+
+    #[test]
+    fn svg() {
+        let svg = map().usage("fs", 0x20000).to_svg();
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.ends_with("</svg>\n"));
+        // a rect per region, one for the usage of fs and three dashed free regions
+        assert_eq!(svg.matches("<rect").count(), 9);
+        assert_eq!(svg.matches("stroke-dasharray").count(), 3);
+        assert!(svg.contains("&lt;partition table&gt;"));
+        assert!(svg.contains("512K (25%)"));
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(format_size(512), "512");
+        assert_eq!(format_size(0x6000), "24K");
+        assert_eq!(format_size(0x1880), "6.1K");
+        assert_eq!(format_size(0x180000), "1.5M");
+    }
+}
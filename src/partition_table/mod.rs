@@ -1,63 +1,445 @@
+mod csv;
+mod diff;
+mod error;
+mod map;
+mod plan;
+mod registry;
+mod scan;
+mod validate;
+
 use std::fs::File;
 use std::io::Read;
 
 use nom::bytes::complete::{tag, take};
 use nom::combinator::map;
-use nom::multi::many_m_n;
 use nom::number::complete::{le_u32, le_u8};
 use nom::{IResult, InputIter};
 
+pub use csv::parse_int;
+pub use diff::{Change, MigrationIssue, MigrationIssueKind};
+pub use error::{CsvError, CsvErrorKind, PartitionTableError, PlanError};
+pub use map::{MemoryMap, Region, RegionKind};
+pub use plan::PartitionSpec;
+pub use registry::NameRegistry;
+pub use scan::{Candidate, Md5Status};
+pub use validate::{Diagnostic, DiagnosticKind, Severity};
+
+/// The partition is encrypted when flash encryption is enabled
+pub const FLAG_ENCRYPTED: u32 = 1 << 0;
+/// The partition can not be written to by the app
+pub const FLAG_READONLY: u32 = 1 << 1;
+
+/// Offset the esp-idf places the partition table at unless configured otherwise
+pub const DEFAULT_TABLE_OFFSET: u32 = 0x8000;
+
+/// Size of a single entry in the binary partition table
+const ENTRY_SIZE: usize = 32;
+/// The table region is 0xC00 bytes which leaves room for 95 partitions after
+/// accounting for the MD5 record
+pub const MAX_PARTITIONS: usize = 95;
+/// Size of the binary partition table
+pub const TABLE_SIZE: usize = 0xc00;
+/// Size of the flash region reserved for the partition table, partitions can't
+/// start until after it
+pub const TABLE_REGION_SIZE: u32 = 0x1000;
+/// App partitions must start on a 64K boundary, everything else on a 4K boundary
+pub const APP_ALIGNMENT: u32 = 0x10000;
+pub const DATA_ALIGNMENT: u32 = 0x1000;
+/// The MD5 record starts with 0xEBEB followed by 14 bytes of 0xFF, the last 16
+/// bytes of the entry are the digest of all of the preceding entries
+const MD5_MAGIC: [u8; 16] = [
+    0xeb, 0xeb, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
 fn parse_partition(input: &[u8]) -> IResult<&[u8], Partition> {
     let (input, _) = tag(&[0xaa, 0x50])(input)?;
     let (input, partition_type) = map(le_u8, |val: u8| PartitionType::from(val))(input)?;
     let (input, subtype) = map(le_u8, |subtype| Subtype::new(&partition_type, subtype))(input)?;
     let (input, offset) = le_u32(input)?;
     let (input, size) = le_u32(input)?;
-    let (input, name) = map(take(16usize), |bytes: &[u8]| {
-        let end = bytes.position(|c| c == 0);
-        match end {
-            Some(end) => String::from_utf8_lossy(&bytes[0..end]).to_string(),
-            None => String::from(""),
-        }
-    })(input)?;
+    let (input, raw_name) = take(16usize)(input)?;
+    // a name using all 16 bytes has no null terminator
+    let end = raw_name.position(|c| c == 0).unwrap_or(raw_name.len());
+    let name = String::from_utf8_lossy(&raw_name[0..end]).to_string();
     let (input, flags) = le_u32(input)?;
-    let partition = Partition::new(name, partition_type, subtype, offset, size, flags);
+    let mut partition = Partition::new(name, partition_type, subtype, offset, size, flags);
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(raw_name);
+    partition.raw_name = Some(bytes);
     Ok((input, partition))
 }
 
-fn parse_hash(input: &[u8]) -> IResult<&[u8], Vec<u8>> {
-    let (input, hash) = map(take(32usize), |bytes: &[u8]| bytes.to_vec())(input)?;
-    Ok((input, hash))
+fn parse_md5(input: &[u8]) -> IResult<&[u8], [u8; 16]> {
+    let (input, _) = tag(&MD5_MAGIC[..])(input)?;
+    let (input, digest) = take(16usize)(input)?;
+    let mut md5 = [0; 16];
+    md5.copy_from_slice(digest);
+    Ok((input, md5))
 }
 
 #[derive(Debug, Clone)]
 pub struct PartitionTable {
     partitions: Vec<Partition>,
-    hash: Vec<u8>,
+    /// The digest stored in the MD5 record, tables generated with
+    /// `--disable-md5sum` do not have one
+    md5: Option<[u8; 16]>,
 }
 
 impl PartitionTable {
+    #[deprecated(note = "use `parse`, which returns an error instead of panicking")]
     pub fn new(input: &[u8]) -> PartitionTable {
-        let (input, partitions) = many_m_n(1, 95, parse_partition)(input).unwrap();
-        let (_, hash) = parse_hash(input).unwrap();
+        PartitionTable::parse(input).unwrap()
+    }
+
+    /// Create a table from a list of partitions, including an MD5 record the same
+    /// as gen_esp32part.py does by default
+    pub fn from_partitions(partitions: Vec<Partition>) -> PartitionTable {
+        let mut table = PartitionTable {
+            partitions,
+            md5: None,
+        };
+        table.md5 = Some(table.calculate_md5());
+        table
+    }
+
+    /// Parse a binary partition table. `input` should start at the beginning of
+    /// the table, which ends at either the MD5 record or an entry of all 0xFF for
+    /// tables without one. Anything after that is ignored.
+    pub fn parse(input: &[u8]) -> Result<PartitionTable, PartitionTableError> {
+        let mut partitions = vec![];
+        let mut offset = 0;
+        loop {
+            let index = partitions.len();
+            let entry = &input[offset..];
+            if entry.is_empty() && index > 0 {
+                return Err(PartitionTableError::MissingMd5 { offset });
+            } else if entry.len() < ENTRY_SIZE {
+                return Err(PartitionTableError::TruncatedEntry { index, offset });
+            }
+
+            match entry[0..2] {
+                [0xaa, 0x50] if index == MAX_PARTITIONS => {
+                    return Err(PartitionTableError::TooManyEntries { offset })
+                }
+                [0xaa, 0x50] => {
+                    let (_, partition) = parse_partition(entry)
+                        .map_err(|_| PartitionTableError::TruncatedEntry { index, offset })?;
+                    partitions.push(partition);
+                }
+                [0xeb, 0xeb] if index > 0 => {
+                    let (_, md5) = parse_md5(entry)
+                        .map_err(|_| PartitionTableError::MissingMagic { index, offset })?;
+                    return Ok(PartitionTable {
+                        partitions,
+                        md5: Some(md5),
+                    });
+                }
+                _ if index > 0 && entry[0..ENTRY_SIZE].iter().all(|b| *b == 0xff) => {
+                    return Ok(PartitionTable {
+                        partitions,
+                        md5: None,
+                    });
+                }
+                _ => return Err(PartitionTableError::MissingMagic { index, offset }),
+            }
 
-        PartitionTable { partitions, hash }
+            offset += ENTRY_SIZE;
+        }
     }
 
-    pub fn from_file(filename: &str) -> PartitionTable {
-        let mut file = File::open(filename).unwrap();
+    pub fn from_file(filename: &str) -> Result<PartitionTable, PartitionTableError> {
+        let mut file = File::open(filename)?;
         let mut data = vec![];
-        file.read_to_end(&mut data).unwrap();
+        file.read_to_end(&mut data)?;
+
+        PartitionTable::parse(&data)
+    }
 
-        PartitionTable::new(&data)
+    /// Lay out `specs` in order for a table at the default offset of 0x8000. App
+    /// partitions are aligned to 64K and everything else to 4K.
+    pub fn plan(specs: &[PartitionSpec], flash_size: u32) -> Result<PartitionTable, PlanError> {
+        PartitionTable::plan_with_offset(specs, DEFAULT_TABLE_OFFSET, flash_size)
+    }
+
+    /// Lay out `specs` in order for a table flashed at `table_offset`
+    pub fn plan_with_offset(
+        specs: &[PartitionSpec],
+        table_offset: u32,
+        flash_size: u32,
+    ) -> Result<PartitionTable, PlanError> {
+        let partitions = plan::plan(specs, table_offset, flash_size)?;
+        Ok(PartitionTable::from_partitions(partitions))
+    }
+
+    /// Re-plan the table for a flash of `flash_size`, for a table at the default
+    /// offset. The partition named `fill` grows in place to take up the remaining
+    /// space and only the partitions after it move to make room, which fails if
+    /// one of them is named in `pinned`. Without `fill` nothing moves.
+    pub fn grow(
+        &self,
+        flash_size: u32,
+        pinned: &[&str],
+        fill: Option<&str>,
+    ) -> Result<PartitionTable, PlanError> {
+        self.grow_with_offset(DEFAULT_TABLE_OFFSET, flash_size, pinned, fill)
+    }
+
+    /// Re-plan the table for a table flashed at `table_offset`
+    pub fn grow_with_offset(
+        &self,
+        table_offset: u32,
+        flash_size: u32,
+        pinned: &[&str],
+        fill: Option<&str>,
+    ) -> Result<PartitionTable, PlanError> {
+        let target = match fill {
+            Some(name) => {
+                Some(
+                    self.find_by_name(name)
+                        .ok_or_else(|| PlanError::UnknownPartition {
+                            name: name.to_owned(),
+                        })?,
+                )
+            }
+            None => None,
+        };
+
+        // the partitions that move are placed after the target in their old order
+        let mut partitions: Vec<&Partition> = self.partitions.iter().collect();
+        partitions.sort_by_key(|partition| partition.offset);
+
+        let mut specs: Vec<PartitionSpec> = Vec::with_capacity(partitions.len());
+        for partition in partitions {
+            let spec = PartitionSpec::from_partition(partition);
+            let spec = match target {
+                Some(target) if partition.name == target.name => spec.pin(partition.offset).fill(),
+                Some(target) if partition.offset > target.offset => {
+                    if pinned.contains(&partition.name.as_str()) {
+                        return Err(PlanError::PinnedInTheWay {
+                            name: target.name.clone(),
+                            pinned: partition.name.clone(),
+                        });
+                    }
+                    spec
+                }
+                _ => spec.pin(partition.offset),
+            };
+            specs.push(spec);
+        }
+
+        PartitionTable::plan_with_offset(&specs, table_offset, flash_size)
+    }
+
+    /// Search a binary of unknown layout, such as a flash dump, for partition
+    /// tables. The most likely table is first.
+    pub fn scan(input: &[u8]) -> Vec<Candidate> {
+        scan::scan(input)
+    }
+
+    /// Parse a partitions.csv in the format accepted by gen_esp32part.py for a
+    /// table flashed at the default offset of 0x8000
+    pub fn from_csv(input: &str) -> Result<PartitionTable, CsvError> {
+        PartitionTable::from_csv_with_offset(input, DEFAULT_TABLE_OFFSET)
+    }
+
+    /// Parse a partitions.csv for a table flashed at `table_offset`, which
+    /// determines where partitions without an explicit offset are placed
+    pub fn from_csv_with_offset(
+        input: &str,
+        table_offset: u32,
+    ) -> Result<PartitionTable, CsvError> {
+        PartitionTable::from_csv_with_names(input, table_offset, &NameRegistry::new())
+    }
+
+    /// Parse a partitions.csv that uses custom type and subtype names from `names`
+    pub fn from_csv_with_names(
+        input: &str,
+        table_offset: u32,
+        names: &NameRegistry,
+    ) -> Result<PartitionTable, CsvError> {
+        let partitions = csv::parse(input, table_offset, names)?;
+        Ok(PartitionTable::from_partitions(partitions))
+    }
+
+    /// Render the table as a partitions.csv that gen_esp32part.py accepts
+    pub fn to_csv(&self) -> String {
+        self.to_csv_with_names(&NameRegistry::new())
+    }
+
+    /// Render the table as a partitions.csv, using the names from `names` for
+    /// custom types and subtypes
+    pub fn to_csv_with_names(&self, names: &NameRegistry) -> String {
+        csv::write(&self.partitions, names)
+    }
+
+    /// Check the table for problems, for a table at the default offset of 0x8000.
+    /// Partitions are also checked against the size of the flash if one is given.
+    pub fn validate(&self, flash_size: Option<u32>) -> Vec<Diagnostic> {
+        self.validate_with_offset(DEFAULT_TABLE_OFFSET, flash_size)
+    }
+
+    /// Check the table for problems when it is flashed at `table_offset`
+    pub fn validate_with_offset(
+        &self,
+        table_offset: u32,
+        flash_size: Option<u32>,
+    ) -> Vec<Diagnostic> {
+        self.validate_with_names(table_offset, flash_size, None, &NameRegistry::new())
+    }
+
+    /// Check the table for problems, also warning about types and subtypes that
+    /// are not supported by `idf_version`
+    pub fn validate_for_idf_version(
+        &self,
+        table_offset: u32,
+        flash_size: Option<u32>,
+        idf_version: IdfVersion,
+    ) -> Vec<Diagnostic> {
+        self.validate_with_names(
+            table_offset,
+            flash_size,
+            Some(idf_version),
+            &NameRegistry::new(),
+        )
+    }
+
+    /// Check the table for problems, the custom types and subtypes registered in
+    /// `names` are expected and not reported as invalid
+    pub fn validate_with_names(
+        &self,
+        table_offset: u32,
+        flash_size: Option<u32>,
+        idf_version: Option<IdfVersion>,
+        names: &NameRegistry,
+    ) -> Vec<Diagnostic> {
+        validate::validate(
+            &self.partitions,
+            table_offset,
+            flash_size,
+            idf_version,
+            names,
+        )
+    }
+
+    /// The partitions that were added, removed or changed in `new`
+    pub fn diff(&self, new: &PartitionTable) -> Vec<Change> {
+        diff::diff(self, new)
+    }
+
+    /// Check for problems updating a device using this table to `new` over OTA,
+    /// such as losing the data in NVS. App slots are also checked against the
+    /// size of the firmware if one is given.
+    pub fn check_migration(
+        &self,
+        new: &PartitionTable,
+        firmware_size: Option<u32>,
+    ) -> Vec<MigrationIssue> {
+        diff::check_migration(self, new, firmware_size)
+    }
+
+    /// The layout of the flash for the table at the default offset of 0x8000, see
+    /// `MemoryMap::new`
+    pub fn memory_map(&self, flash_size: Option<u32>) -> MemoryMap {
+        MemoryMap::new(self, DEFAULT_TABLE_OFFSET, flash_size)
     }
 
     pub fn partitions(&self) -> &[Partition] {
         &self.partitions
     }
 
-    pub fn hash(&self) -> &[u8] {
-        &self.hash
+    /// Find the partition with the given name (label)
+    pub fn find_by_name(&self, name: &str) -> Option<&Partition> {
+        self.partitions
+            .iter()
+            .find(|partition| partition.name == name)
+    }
+
+    /// Find partitions the same way as `esp_partition_find`. `PartitionType::Any`
+    /// and `Subtype::Any` match every type or subtype and a name of `None` matches
+    /// every name. Partitions are returned in the order they appear in the table.
+    pub fn find(
+        &self,
+        partition_type: &PartitionType,
+        subtype: &Subtype,
+        name: Option<&str>,
+    ) -> Vec<&Partition> {
+        self.partitions
+            .iter()
+            .filter(|partition| {
+                *partition_type == PartitionType::Any || partition.partition_type == *partition_type
+            })
+            .filter(|partition| *subtype == Subtype::Any || partition.subtype == *subtype)
+            .filter(|partition| name.map_or(true, |name| partition.name == name))
+            .collect()
+    }
+
+    /// Find the partition that `address` falls within
+    pub fn find_by_address(&self, address: u32) -> Option<&Partition> {
+        self.partitions
+            .iter()
+            .find(|partition| partition.contains(address))
+    }
+
+    /// The OTA app partitions ordered by their slot number (ota_0, ota_1, ...)
+    pub fn ota_slots(&self) -> Vec<&Partition> {
+        let mut slots: Vec<&Partition> = self
+            .partitions
+            .iter()
+            .filter(|partition| partition.subtype.ota_slot().is_some())
+            .collect();
+        slots.sort_by_key(|partition| partition.subtype.ota_slot());
+        slots
+    }
+
+    /// The digest from the MD5 record if the table has one
+    pub fn md5(&self) -> Option<&[u8; 16]> {
+        self.md5.as_ref()
+    }
+
+    /// Calculate the MD5 digest of the binary entries in the table, this is what
+    /// the bootloader compares against the MD5 record
+    pub fn calculate_md5(&self) -> [u8; 16] {
+        let mut entries = Vec::with_capacity(self.partitions.len() * ENTRY_SIZE);
+        for partition in &self.partitions {
+            entries.extend_from_slice(&partition.to_bytes());
+        }
+
+        md5::compute(&entries).0
+    }
+
+    /// Serialize the table into the binary format flashed to the device. The MD5
+    /// record is included if the table has one and the result is padded with 0xFF
+    /// out to the full size of the partition table region. A parsed table keeps
+    /// the digest it was read with, even one that doesn't match, so it is written
+    /// back byte for byte.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_bytes_with_md5(self.md5.as_ref())
+    }
+
+    /// Serialize the table with the given digest in the MD5 record, or without
+    /// an MD5 record for `None`. Use `calculate_md5` to write a correct digest.
+    pub fn to_bytes_with_md5(&self, md5: Option<&[u8; 16]>) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(TABLE_SIZE);
+        for partition in &self.partitions {
+            bytes.extend_from_slice(&partition.to_bytes());
+        }
+
+        if let Some(md5) = md5 {
+            bytes.extend_from_slice(&MD5_MAGIC);
+            bytes.extend_from_slice(md5);
+        }
+
+        if bytes.len() < TABLE_SIZE {
+            bytes.resize(TABLE_SIZE, 0xff);
+        }
+
+        bytes
+    }
+
+    /// Whether the MD5 record matches the entries in the table. This is `None`
+    /// when the table does not have an MD5 record.
+    pub fn md5_matches(&self) -> Option<bool> {
+        self.md5.map(|md5| md5 == self.calculate_md5())
     }
 }
 
@@ -69,6 +451,9 @@ pub struct Partition {
     offset: u32,
     size: u32,
     flags: u32,
+    /// The name field as stored in a binary table, so names that aren't utf-8 or
+    /// have bytes after the terminator are written back unchanged
+    raw_name: Option<[u8; 16]>,
 }
 
 impl Partition {
@@ -87,14 +472,83 @@ impl Partition {
             offset,
             size,
             flags,
+            raw_name: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn partition_type(&self) -> &PartitionType {
+        &self.partition_type
+    }
+
+    pub fn subtype(&self) -> &Subtype {
+        &self.subtype
+    }
+
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// The address directly after the end of the partition. This is a u64 as a
+    /// partition can end exactly at the 4G limit of the address space.
+    pub fn end(&self) -> u64 {
+        self.offset as u64 + self.size as u64
+    }
+
+    /// The raw flags, see `is_encrypted` and `is_readonly` for the decoded values
+    pub fn flags(&self) -> u32 {
+        self.flags
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & FLAG_ENCRYPTED != 0
+    }
+
+    pub fn is_readonly(&self) -> bool {
+        self.flags & FLAG_READONLY != 0
+    }
+
+    /// Whether `address` is within the partition
+    pub fn contains(&self, address: u32) -> bool {
+        address >= self.offset && (address as u64) < self.end()
+    }
+
+    /// Binary representation of the partition as stored in the partition table
+    pub(crate) fn to_bytes(&self) -> [u8; ENTRY_SIZE] {
+        let mut bytes = [0; ENTRY_SIZE];
+        bytes[0..2].copy_from_slice(&[0xaa, 0x50]);
+        bytes[2] = self.partition_type.value();
+        bytes[3] = self.subtype.value();
+        bytes[4..8].copy_from_slice(&self.offset.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.size.to_le_bytes());
+        match &self.raw_name {
+            Some(raw_name) => bytes[12..28].copy_from_slice(raw_name),
+            None => {
+                let name = self.name.as_bytes();
+                let len = name.len().min(16);
+                bytes[12..12 + len].copy_from_slice(&name[0..len]);
+            }
         }
+        bytes[28..32].copy_from_slice(&self.flags.to_le_bytes());
+        bytes
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PartitionType {
     App,
     Data,
+    /// A bootloader image, this lets the bootloader region be updated over OTA
+    Bootloader,
+    /// A partition table image, this lets the table region be updated over OTA
+    PartitionTable,
     Any,
     /// Custom partition types can be added to be used for partitions not part of the core esp-idf.
     /// These can be in the range of 64-255
@@ -104,11 +558,59 @@ pub enum PartitionType {
     Invalid(u8),
 }
 
+impl PartitionType {
+    /// The numeric value of the type as stored in the partition table
+    pub fn value(&self) -> u8 {
+        match self {
+            Self::App => 0,
+            Self::Data => 1,
+            Self::Bootloader => 2,
+            Self::PartitionTable => 3,
+            Self::Any => 255,
+            Self::Custom(value) => *value,
+            Self::Invalid(value) => *value,
+        }
+    }
+
+    /// The name used for the type in a partitions.csv if it has one
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Self::App => Some("app"),
+            Self::Data => Some("data"),
+            Self::Bootloader => Some("bootloader"),
+            Self::PartitionTable => Some("partition_table"),
+            _ => None,
+        }
+    }
+
+    /// The first esp-idf release that accepts the type, `None` when every release
+    /// does
+    pub fn min_idf_version(&self) -> Option<IdfVersion> {
+        match self {
+            Self::Bootloader | Self::PartitionTable => Some(IdfVersion::new(5, 3)),
+            _ => None,
+        }
+    }
+
+    /// Look up a type by the name used in a partitions.csv
+    pub fn from_name(name: &str) -> Option<PartitionType> {
+        match name {
+            "app" => Some(Self::App),
+            "data" => Some(Self::Data),
+            "bootloader" => Some(Self::Bootloader),
+            "partition_table" => Some(Self::PartitionTable),
+            _ => None,
+        }
+    }
+}
+
 impl From<u8> for PartitionType {
     fn from(value: u8) -> Self {
         match value {
             0 => Self::App,
             1 => Self::Data,
+            2 => Self::Bootloader,
+            3 => Self::PartitionTable,
             64..=254 => Self::Custom(value),
             255 => Self::Any,
             _ => Self::Invalid(value),
@@ -116,7 +618,7 @@ impl From<u8> for PartitionType {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Subtype {
     AppFactory,
     AppOta0,
@@ -136,15 +638,28 @@ pub enum Subtype {
     AppOta14,
     AppOta15,
     AppTest,
+    /// Trusted execution environment app slots
+    AppTee0,
+    AppTee1,
     DataOta,
     DataPhy,
     DataNvs,
     DataCoreDump,
     DataNvsKeys,
     DataEfuse,
+    /// A data partition without a specific subtype
+    DataUndefined,
     DataEspHttpd,
     DataFat,
     DataSpiffs,
+    DataLittleFs,
+    /// Holds the state used to select between the TEE app slots
+    DataTeeOta,
+    BootloaderPrimary,
+    BootloaderOta,
+    BootloaderRecovery,
+    PartitionTablePrimary,
+    PartitionTableOta,
     Any,
     Invalid(u8),
     Custom(u8),
@@ -172,6 +687,8 @@ impl Subtype {
                 30 => Subtype::AppOta14,
                 31 => Subtype::AppOta15,
                 32 => Subtype::AppTest,
+                48 => Subtype::AppTee0,
+                49 => Subtype::AppTee1,
                 _ => Subtype::Invalid(subtype_value),
             },
             PartitionType::Data => match subtype_value {
@@ -181,9 +698,23 @@ impl Subtype {
                 3 => Subtype::DataCoreDump,
                 4 => Subtype::DataNvsKeys,
                 5 => Subtype::DataEfuse,
+                6 => Subtype::DataUndefined,
                 128 => Subtype::DataEspHttpd,
                 129 => Subtype::DataFat,
                 130 => Subtype::DataSpiffs,
+                131 => Subtype::DataLittleFs,
+                144 => Subtype::DataTeeOta,
+                _ => Subtype::Invalid(subtype_value),
+            },
+            PartitionType::Bootloader => match subtype_value {
+                0 => Subtype::BootloaderPrimary,
+                1 => Subtype::BootloaderOta,
+                2 => Subtype::BootloaderRecovery,
+                _ => Subtype::Invalid(subtype_value),
+            },
+            PartitionType::PartitionTable => match subtype_value {
+                0 => Subtype::PartitionTablePrimary,
+                1 => Subtype::PartitionTableOta,
                 _ => Subtype::Invalid(subtype_value),
             },
             PartitionType::Custom(_) => match subtype_value {
@@ -194,4 +725,342 @@ impl Subtype {
             PartitionType::Any => Subtype::Any,
         }
     }
+
+    /// The numeric value of the subtype as stored in the partition table
+    pub fn value(&self) -> u8 {
+        match self {
+            Subtype::AppFactory => 0,
+            Subtype::AppOta0 => 16,
+            Subtype::AppOta1 => 17,
+            Subtype::AppOta2 => 18,
+            Subtype::AppOta3 => 19,
+            Subtype::AppOta4 => 20,
+            Subtype::AppOta5 => 21,
+            Subtype::AppOta6 => 22,
+            Subtype::AppOta7 => 23,
+            Subtype::AppOta8 => 24,
+            Subtype::AppOta9 => 25,
+            Subtype::AppOta10 => 26,
+            Subtype::AppOta11 => 27,
+            Subtype::AppOta12 => 28,
+            Subtype::AppOta13 => 29,
+            Subtype::AppOta14 => 30,
+            Subtype::AppOta15 => 31,
+            Subtype::AppTest => 32,
+            Subtype::AppTee0 => 48,
+            Subtype::AppTee1 => 49,
+            Subtype::DataOta => 0,
+            Subtype::DataPhy => 1,
+            Subtype::DataNvs => 2,
+            Subtype::DataCoreDump => 3,
+            Subtype::DataNvsKeys => 4,
+            Subtype::DataEfuse => 5,
+            Subtype::DataUndefined => 6,
+            Subtype::DataEspHttpd => 128,
+            Subtype::DataFat => 129,
+            Subtype::DataSpiffs => 130,
+            Subtype::DataLittleFs => 131,
+            Subtype::DataTeeOta => 144,
+            Subtype::BootloaderPrimary => 0,
+            Subtype::BootloaderOta => 1,
+            Subtype::BootloaderRecovery => 2,
+            Subtype::PartitionTablePrimary => 0,
+            Subtype::PartitionTableOta => 1,
+            Subtype::Any => 255,
+            Subtype::Invalid(value) => *value,
+            Subtype::Custom(value) => *value,
+        }
+    }
+
+    /// The slot number for OTA app subtypes, 0 for ota_0 through 15 for ota_15
+    pub fn ota_slot(&self) -> Option<u8> {
+        match self {
+            Subtype::AppOta0
+            | Subtype::AppOta1
+            | Subtype::AppOta2
+            | Subtype::AppOta3
+            | Subtype::AppOta4
+            | Subtype::AppOta5
+            | Subtype::AppOta6
+            | Subtype::AppOta7
+            | Subtype::AppOta8
+            | Subtype::AppOta9
+            | Subtype::AppOta10
+            | Subtype::AppOta11
+            | Subtype::AppOta12
+            | Subtype::AppOta13
+            | Subtype::AppOta14
+            | Subtype::AppOta15 => Some(self.value() - 16),
+            _ => None,
+        }
+    }
+
+    /// The name used for the subtype in a partitions.csv if it has one
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            Subtype::AppFactory => Some("factory"),
+            Subtype::AppOta0 => Some("ota_0"),
+            Subtype::AppOta1 => Some("ota_1"),
+            Subtype::AppOta2 => Some("ota_2"),
+            Subtype::AppOta3 => Some("ota_3"),
+            Subtype::AppOta4 => Some("ota_4"),
+            Subtype::AppOta5 => Some("ota_5"),
+            Subtype::AppOta6 => Some("ota_6"),
+            Subtype::AppOta7 => Some("ota_7"),
+            Subtype::AppOta8 => Some("ota_8"),
+            Subtype::AppOta9 => Some("ota_9"),
+            Subtype::AppOta10 => Some("ota_10"),
+            Subtype::AppOta11 => Some("ota_11"),
+            Subtype::AppOta12 => Some("ota_12"),
+            Subtype::AppOta13 => Some("ota_13"),
+            Subtype::AppOta14 => Some("ota_14"),
+            Subtype::AppOta15 => Some("ota_15"),
+            Subtype::AppTest => Some("test"),
+            Subtype::AppTee0 => Some("tee_0"),
+            Subtype::AppTee1 => Some("tee_1"),
+            Subtype::DataOta => Some("ota"),
+            Subtype::DataPhy => Some("phy"),
+            Subtype::DataNvs => Some("nvs"),
+            Subtype::DataCoreDump => Some("coredump"),
+            Subtype::DataNvsKeys => Some("nvs_keys"),
+            Subtype::DataEfuse => Some("efuse"),
+            Subtype::DataUndefined => Some("undefined"),
+            Subtype::DataEspHttpd => Some("esphttpd"),
+            Subtype::DataFat => Some("fat"),
+            Subtype::DataSpiffs => Some("spiffs"),
+            Subtype::DataLittleFs => Some("littlefs"),
+            Subtype::DataTeeOta => Some("tee_ota"),
+            Subtype::BootloaderPrimary | Subtype::PartitionTablePrimary => Some("primary"),
+            Subtype::BootloaderOta | Subtype::PartitionTableOta => Some("ota"),
+            Subtype::BootloaderRecovery => Some("recovery"),
+            Subtype::Any | Subtype::Invalid(_) | Subtype::Custom(_) => None,
+        }
+    }
+
+    /// The first esp-idf release that accepts the subtype, `None` when every
+    /// release does
+    pub fn min_idf_version(&self) -> Option<IdfVersion> {
+        match self {
+            Subtype::DataNvsKeys | Subtype::DataEfuse => Some(IdfVersion::new(4, 0)),
+            Subtype::DataUndefined => Some(IdfVersion::new(4, 4)),
+            Subtype::DataLittleFs
+            | Subtype::BootloaderPrimary
+            | Subtype::BootloaderOta
+            | Subtype::PartitionTablePrimary
+            | Subtype::PartitionTableOta => Some(IdfVersion::new(5, 3)),
+            Subtype::BootloaderRecovery
+            | Subtype::AppTee0
+            | Subtype::AppTee1
+            | Subtype::DataTeeOta => Some(IdfVersion::new(5, 5)),
+            _ => None,
+        }
+    }
+
+    /// Look up a subtype of `partition_type` by the name used in a partitions.csv
+    pub fn from_name(partition_type: &PartitionType, name: &str) -> Option<Subtype> {
+        (0..=254)
+            .map(|value| Subtype::new(partition_type, value))
+            .find(|subtype| subtype.name() == Some(name))
+    }
+}
+
+/// An esp-idf release, used to check that a table is supported by the version a
+/// project is built with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IdfVersion {
+    major: u8,
+    minor: u8,
+}
+
+impl IdfVersion {
+    pub fn new(major: u8, minor: u8) -> IdfVersion {
+        IdfVersion { major, minor }
+    }
+
+    /// Parse a version such as `5.3` or `v5.3`, the patch version is ignored
+    pub fn parse(value: &str) -> Option<IdfVersion> {
+        let value = value.strip_prefix('v').unwrap_or(value);
+        let mut parts = value.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = match parts.next() {
+            Some(minor) => minor.parse().ok()?,
+            None => 0,
+        };
+
+        Some(IdfVersion::new(major, minor))
+    }
+
+    pub fn major(&self) -> u8 {
+        self.major
+    }
+
+    pub fn minor(&self) -> u8 {
+        self.minor
+    }
+}
+
+impl std::fmt::Display for IdfVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "v{}.{}", self.major, self.minor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> PartitionTable {
+        PartitionTable::from_csv("nvs,data,nvs,0x9000,24K\nfactory,app,factory,0x10000,1M\n")
+            .unwrap()
+    }
+
+    #[test]
+    fn to_bytes_round_trip() {
+        let mut data = table().to_bytes();
+        // a name that isn't utf-8 with bytes after the terminator
+        data[12..16].copy_from_slice(&[0xff, 0xfe, 0x00, 0x41]);
+        // a digest that doesn't match the entries
+        data[2 * ENTRY_SIZE + 16] ^= 0x01;
+
+        let parsed = PartitionTable::parse(&data).unwrap();
+        assert_eq!(parsed.md5_matches(), Some(false));
+        assert_eq!(parsed.to_bytes(), data);
+
+        let md5 = parsed.calculate_md5();
+        let fixed = PartitionTable::parse(&parsed.to_bytes_with_md5(Some(&md5))).unwrap();
+        assert_eq!(fixed.md5_matches(), Some(true));
+    }
+
+    #[test]
+    fn to_bytes_without_md5() {
+        let data = table().to_bytes_with_md5(None);
+        assert!(data[2 * ENTRY_SIZE..].iter().all(|b| *b == 0xff));
+
+        let parsed = PartitionTable::parse(&data).unwrap();
+        assert_eq!(parsed.md5(), None);
+        assert_eq!(parsed.to_bytes(), data);
+    }
+
+    /// A raw entry for an nvs partition, laid out the same as esp_partition_info_t
+    fn raw_entry(name: &str, offset: u32) -> Vec<u8> {
+        let mut entry = vec![0xaa, 0x50, 0x01, 0x02];
+        entry.extend_from_slice(&offset.to_le_bytes());
+        entry.extend_from_slice(&0x1000u32.to_le_bytes());
+        let mut label = [0u8; 16];
+        label[..name.len()].copy_from_slice(name.as_bytes());
+        entry.extend_from_slice(&label);
+        entry.extend_from_slice(&0u32.to_le_bytes());
+        entry
+    }
+
+    #[test]
+    fn parse_errors() {
+        let nvs = raw_entry("nvs", 0x9000);
+
+        assert!(matches!(
+            PartitionTable::parse(&[]),
+            Err(PartitionTableError::TruncatedEntry {
+                index: 0,
+                offset: 0
+            })
+        ));
+        assert!(matches!(
+            PartitionTable::parse(&[0x5a; 64]),
+            Err(PartitionTableError::MissingMagic {
+                index: 0,
+                offset: 0
+            })
+        ));
+        assert!(matches!(
+            PartitionTable::parse(&[&nvs[..], &nvs[..12]].concat()),
+            Err(PartitionTableError::TruncatedEntry {
+                index: 1,
+                offset: 0x20
+            })
+        ));
+        assert!(matches!(
+            PartitionTable::parse(&nvs),
+            Err(PartitionTableError::MissingMd5 { offset: 0x20 })
+        ));
+
+        let err = PartitionTable::parse(&[&nvs[..], &[0u8; 32][..]].concat()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "entry 1 at offset 0x20 is missing the partition magic"
+        );
+
+        let full: Vec<u8> = (0..=MAX_PARTITIONS as u32)
+            .flat_map(|i| raw_entry("data", 0x9000 + i * 0x1000))
+            .collect();
+        assert!(matches!(
+            PartitionTable::parse(&full),
+            Err(PartitionTableError::TooManyEntries { offset }) if offset == MAX_PARTITIONS * ENTRY_SIZE
+        ));
+
+        let table = PartitionTable::parse(&[&nvs[..], &[0xff; 32][..]].concat()).unwrap();
+        assert_eq!(table.partitions()[0].name(), "nvs");
+    }
+
+    #[test]
+    fn lookups() {
+        let part = |name: &str, partition_type, subtype, offset, flags| {
+            Partition::new(
+                name.to_owned(),
+                partition_type,
+                subtype,
+                offset,
+                0x10000,
+                flags,
+            )
+        };
+        let table = PartitionTable::from_partitions(vec![
+            part("otadata", PartitionType::Data, Subtype::DataOta, 0x50000, 0),
+            part("ota_1", PartitionType::App, Subtype::AppOta1, 0x20000, 0),
+            part(
+                "ota_0",
+                PartitionType::App,
+                Subtype::AppOta0,
+                0x10000,
+                FLAG_ENCRYPTED,
+            ),
+            part(
+                "keys",
+                PartitionType::Data,
+                Subtype::DataNvsKeys,
+                0x30000,
+                FLAG_READONLY,
+            ),
+        ]);
+
+        let keys = table.find_by_name("keys").unwrap();
+        assert!(keys.is_readonly() && !keys.is_encrypted());
+        assert!(table.find_by_name("ota_0").unwrap().is_encrypted());
+        assert!(table.find_by_name("factory").is_none());
+
+        let names = |partitions: Vec<&Partition>| -> Vec<String> {
+            partitions.iter().map(|p| p.name().to_owned()).collect()
+        };
+        assert_eq!(
+            names(table.find(&PartitionType::App, &Subtype::Any, None)),
+            ["ota_1", "ota_0"]
+        );
+        assert_eq!(
+            names(table.find(&PartitionType::Any, &Subtype::DataOta, None)),
+            ["otadata"]
+        );
+        assert_eq!(
+            names(table.find(&PartitionType::Any, &Subtype::Any, Some("keys"))),
+            ["keys"]
+        );
+        assert!(table
+            .find(&PartitionType::Data, &Subtype::AppOta0, None)
+            .is_empty());
+
+        assert_eq!(table.find_by_address(0x2ffff).unwrap().name(), "ota_1");
+        assert_eq!(table.find_by_address(0x30000).unwrap().name(), "keys");
+        assert!(table.find_by_address(0x4ffff).is_none());
+        assert!(table.find_by_address(0x60000).is_none());
+
+        assert_eq!(names(table.ota_slots()), ["ota_0", "ota_1"]);
+    }
 }
@@ -0,0 +1,367 @@
+use crate::partition_table::{
+    Partition, PartitionType, PlanError, Subtype, APP_ALIGNMENT, DATA_ALIGNMENT, TABLE_REGION_SIZE,
+};
+
+/// A partition to be placed by the layout planner
+#[derive(Debug, Clone)]
+pub struct PartitionSpec {
+    name: String,
+    partition_type: PartitionType,
+    subtype: Subtype,
+    size: u32,
+    flags: u32,
+    fill: bool,
+    pinned: Option<u32>,
+}
+
+impl PartitionSpec {
+    /// A partition of at least `size` bytes, the size is rounded up to a multiple
+    /// of the 4K flash sector size
+    pub fn new(name: &str, partition_type: PartitionType, subtype: Subtype, size: u32) -> Self {
+        PartitionSpec {
+            name: name.to_owned(),
+            partition_type,
+            subtype,
+            size,
+            flags: 0,
+            fill: false,
+            pinned: None,
+        }
+    }
+
+    /// A spec with the same name, type, subtype, size and flags as `partition`. It
+    /// is not pinned to the offset of `partition` unless `pin` is used.
+    pub fn from_partition(partition: &Partition) -> Self {
+        PartitionSpec::new(
+            &partition.name,
+            partition.partition_type.clone(),
+            partition.subtype.clone(),
+            partition.size,
+        )
+        .flags(partition.flags)
+    }
+
+    pub fn flags(mut self, flags: u32) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Grow the partition to take up the remaining space in the flash
+    pub fn fill(mut self) -> Self {
+        self.fill = true;
+        self
+    }
+
+    /// Keep the partition at `offset` instead of letting the planner place it
+    pub fn pin(mut self, offset: u32) -> Self {
+        self.pinned = Some(offset);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    fn alignment(&self) -> u32 {
+        match self.partition_type {
+            PartitionType::App => APP_ALIGNMENT,
+            _ => DATA_ALIGNMENT,
+        }
+    }
+}
+
+/// Place `specs` in order after the partition table, leaving pinned partitions
+/// where they are and placing the partitions listed after them further on. The partition marked as fill is grown
+/// to the largest size that still leaves room for everything else.
+pub(crate) fn plan(
+    specs: &[PartitionSpec],
+    table_offset: u32,
+    flash_size: u32,
+) -> Result<Vec<Partition>, PlanError> {
+    let fill = specs.iter().filter(|spec| spec.fill).count();
+    if fill > 1 {
+        return Err(PlanError::MultipleFill);
+    }
+
+    let partitions = place(specs, table_offset, flash_size, None)?;
+    if fill == 0 {
+        return Ok(partitions);
+    }
+
+    // find the largest fill size that fits, in units of a flash sector
+    let min = align_up(
+        specs.iter().find(|spec| spec.fill).unwrap().size as u64,
+        DATA_ALIGNMENT,
+    );
+    let mut low = min / DATA_ALIGNMENT as u64;
+    let mut high = flash_size as u64 / DATA_ALIGNMENT as u64;
+    let mut best = partitions;
+    while low < high {
+        let mid = low + (high - low + 1) / 2;
+        let size = (mid * DATA_ALIGNMENT as u64) as u32;
+        match place(specs, table_offset, flash_size, Some(size)) {
+            Ok(partitions) => {
+                best = partitions;
+                low = mid;
+            }
+            Err(_) => high = mid - 1,
+        }
+    }
+
+    Ok(best)
+}
+
+fn place(
+    specs: &[PartitionSpec],
+    table_offset: u32,
+    flash_size: u32,
+    fill_size: Option<u32>,
+) -> Result<Vec<Partition>, PlanError> {
+    let flash_size = flash_size as u64;
+    let reserved_end = table_offset as u64 + TABLE_REGION_SIZE as u64;
+    // the bootloader and partition table are always reserved
+    let mut used: Vec<(u64, u64)> = vec![(0, reserved_end)];
+
+    for spec in specs {
+        let offset = match spec.pinned {
+            Some(offset) => offset as u64,
+            None => continue,
+        };
+
+        if offset % spec.alignment() as u64 != 0 {
+            return Err(PlanError::MisalignedPin {
+                name: spec.name.clone(),
+                offset: offset as u32,
+            });
+        }
+
+        let end = offset + spec_size(spec, fill_size);
+        if end > flash_size {
+            return Err(PlanError::DoesNotFit {
+                name: spec.name.clone(),
+            });
+        }
+        if used
+            .iter()
+            .any(|(start, stop)| offset < *stop && end > *start)
+        {
+            return Err(PlanError::PinnedOverlap {
+                name: spec.name.clone(),
+            });
+        }
+        used.push((offset, end));
+    }
+
+    let mut partitions = Vec::with_capacity(specs.len());
+    let mut cursor = reserved_end;
+    for spec in specs {
+        let size = spec_size(spec, fill_size);
+        let offset = match spec.pinned {
+            Some(offset) => {
+                cursor = cursor.max(offset as u64 + size);
+                offset as u64
+            }
+            None => {
+                let mut offset = align_up(cursor, spec.alignment());
+                while let Some((_, stop)) = used
+                    .iter()
+                    .find(|(start, stop)| offset < *stop && offset + size > *start)
+                {
+                    offset = align_up(*stop, spec.alignment());
+                }
+
+                if offset + size > flash_size {
+                    return Err(PlanError::DoesNotFit {
+                        name: spec.name.clone(),
+                    });
+                }
+
+                used.push((offset, offset + size));
+                cursor = offset + size;
+                offset
+            }
+        };
+
+        partitions.push(Partition::new(
+            spec.name.clone(),
+            spec.partition_type.clone(),
+            spec.subtype.clone(),
+            offset as u32,
+            size as u32,
+            spec.flags,
+        ));
+    }
+
+    Ok(partitions)
+}
+
+/// Pinned partitions keep their exact size unless they fill the remaining space,
+/// everything else is rounded up to a whole flash sector
+fn spec_size(spec: &PartitionSpec, fill_size: Option<u32>) -> u64 {
+    match fill_size {
+        Some(size) if spec.fill => size as u64,
+        _ if spec.pinned.is_some() => spec.size as u64,
+        _ => align_up(spec.size as u64, DATA_ALIGNMENT),
+    }
+}
+
+fn align_up(value: u64, alignment: u32) -> u64 {
+    (value + alignment as u64 - 1) / alignment as u64 * alignment as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::partition_table::{
+        PartitionSpec, PartitionTable, PartitionType, PlanError, Subtype,
+    };
+
+    const MB: u32 = 1024 * 1024;
+
+    fn layout(table: &PartitionTable) -> Vec<(&str, u32, u32)> {
+        table
+            .partitions()
+            .iter()
+            .map(|partition| (partition.name(), partition.offset(), partition.size()))
+            .collect()
+    }
+
+    fn specs() -> Vec<PartitionSpec> {
+        vec![
+            PartitionSpec::new("nvs", PartitionType::Data, Subtype::DataNvs, 0x5000),
+            PartitionSpec::new("phy_init", PartitionType::Data, Subtype::DataPhy, 0x1000),
+            PartitionSpec::new("factory", PartitionType::App, Subtype::AppFactory, MB),
+        ]
+    }
+
+    #[test]
+    fn plan_places_partitions_after_the_table() {
+        let table = PartitionTable::plan(&specs(), 4 * MB).unwrap();
+        assert_eq!(
+            layout(&table),
+            [
+                ("nvs", 0x9000, 0x5000),
+                ("phy_init", 0xe000, 0x1000),
+                ("factory", 0x10000, MB),
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_fills_remaining_space() {
+        let mut specs = specs();
+        specs[2] = specs[2].clone().fill();
+        let table = PartitionTable::plan(&specs, 4 * MB).unwrap();
+        assert_eq!(layout(&table)[2], ("factory", 0x10000, 4 * MB - 0x10000));
+    }
+
+    #[test]
+    fn plan_skips_over_pinned_partitions() {
+        let mut specs = specs();
+        specs[1] = specs[1].clone().pin(0xa000);
+        let table = PartitionTable::plan(&specs, 4 * MB).unwrap();
+        assert_eq!(
+            layout(&table),
+            [
+                ("nvs", 0xb000, 0x5000),
+                ("phy_init", 0xa000, 0x1000),
+                ("factory", 0x10000, MB),
+            ]
+        );
+    }
+
+    #[test]
+    fn plan_errors() {
+        let plan = |specs: &[PartitionSpec], flash_size| PartitionTable::plan(specs, flash_size);
+
+        let mut fill = specs();
+        fill[0] = fill[0].clone().fill();
+        fill[2] = fill[2].clone().fill();
+        assert!(matches!(plan(&fill, 4 * MB), Err(PlanError::MultipleFill)));
+
+        let mut misaligned = specs();
+        misaligned[2] = misaligned[2].clone().pin(0x18000);
+        assert!(matches!(
+            plan(&misaligned, 4 * MB),
+            Err(PlanError::MisalignedPin { .. })
+        ));
+
+        let mut overlap = specs();
+        overlap[1] = overlap[1].clone().pin(0x8000);
+        assert!(matches!(
+            plan(&overlap, 4 * MB),
+            Err(PlanError::PinnedOverlap { .. })
+        ));
+
+        assert!(matches!(
+            plan(&specs(), MB),
+            Err(PlanError::DoesNotFit { .. })
+        ));
+    }
+
+    #[test]
+    fn grow_keeps_pinned_partitions() {
+        let table = PartitionTable::from_csv(
+            "nvs,data,nvs,0x9000,0x6000\n\
+             phy_init,data,phy,0xf000,0x1000\n\
+             factory,app,factory,0x10000,1M\n\
+             storage,data,fat,0x110000,0x10000\n",
+        )
+        .unwrap();
+
+        let grown = table.grow(4 * MB, &["nvs"], Some("storage")).unwrap();
+        assert_eq!(
+            layout(&grown),
+            [
+                ("nvs", 0x9000, 0x6000),
+                ("phy_init", 0xf000, 0x1000),
+                ("factory", 0x10000, MB),
+                ("storage", 0x110000, 4 * MB - 0x110000),
+            ]
+        );
+    }
+
+    #[test]
+    fn grow_moves_only_the_partitions_after_the_target() {
+        // a gap before the target that the moved partitions must not fall into
+        let table = PartitionTable::from_csv(
+            "nvs,data,nvs,0x9000,0x4000\n\
+             ota_0,app,ota_0,0x10000,0x100000\n\
+             spiffs,data,spiffs,0x110000,0x80000\n\
+             coredump,data,coredump,0x190000,0x2000\n\
+             ota_1,app,ota_1,0x1a0000,0x100000\n",
+        )
+        .unwrap();
+
+        let grown = table.grow(8 * MB, &[], Some("spiffs")).unwrap();
+        assert_eq!(
+            layout(&grown),
+            [
+                ("nvs", 0x9000, 0x4000),
+                ("ota_0", 0x10000, 0x100000),
+                ("spiffs", 0x110000, 8 * MB - 0x102000 - 0x110000),
+                ("coredump", 8 * MB - 0x102000, 0x2000),
+                ("ota_1", 8 * MB - 0x100000, 0x100000),
+            ]
+        );
+
+        let unchanged = table.grow(8 * MB, &[], None).unwrap();
+        assert_eq!(layout(&unchanged), layout(&table));
+
+        assert!(matches!(
+            table.grow(8 * MB, &["ota_1"], Some("spiffs")),
+            Err(PlanError::PinnedInTheWay { name, pinned }) if name == "spiffs" && pinned == "ota_1"
+        ));
+        assert!(matches!(
+            table.grow(8 * MB, &[], Some("fat")),
+            Err(PlanError::UnknownPartition { .. })
+        ));
+        assert!(matches!(
+            table.grow(2 * MB, &[], Some("spiffs")),
+            Err(PlanError::DoesNotFit { .. })
+        ));
+    }
+}
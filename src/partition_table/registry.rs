@@ -0,0 +1,164 @@
+use crate::partition_table::csv;
+use crate::partition_table::{parse_int, CsvError, CsvErrorKind, PartitionType, Subtype};
+
+/// Names for custom partition types and subtypes, such as those a project
+/// declares with `EXTRA_PARTITION_SUBTYPES`. The built in esp-idf names are
+/// always known and take priority over registered ones.
+#[derive(Debug, Clone, Default)]
+pub struct NameRegistry {
+    types: Vec<(u8, String)>,
+    /// The type value, subtype value and name of each subtype
+    subtypes: Vec<(u8, u8, String)>,
+}
+
+impl NameRegistry {
+    pub fn new() -> NameRegistry {
+        NameRegistry::default()
+    }
+
+    /// Parse a registry from a config file. Each line is either a type or a
+    /// subtype, with fields separated by commas the same as a partitions.csv:
+    ///
+    /// ```text
+    /// # type, name, value
+    /// type, sensors, 0x40
+    /// # subtype, type, name, value
+    /// subtype, sensors, calibration, 0x01
+    /// subtype, data, logs, 0x40
+    /// ```
+    ///
+    /// The type of a subtype can be a built in name, a name registered earlier in
+    /// the file or a number.
+    pub fn parse(input: &str) -> Result<NameRegistry, CsvError> {
+        let mut registry = NameRegistry::new();
+        for (i, line) in input.lines().enumerate() {
+            let line_no = i + 1;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+
+            let fields = csv::split_fields(line);
+            let field = |idx: usize, name: &'static str| -> Result<(usize, &str), CsvError> {
+                fields.get(idx).copied().ok_or_else(|| {
+                    CsvError::new(line_no, line.len() + 1, CsvErrorKind::MissingField(name))
+                })
+            };
+            let value = |(column, value): (usize, &str)| -> Result<u8, CsvError> {
+                parse_int(value)
+                    .filter(|value| *value <= 0xfe)
+                    .map(|value| value as u8)
+                    .ok_or_else(|| {
+                        CsvError::new(line_no, column, CsvErrorKind::InvalidNumber(value.into()))
+                    })
+            };
+
+            let (column, kind) = field(0, "kind")?;
+            let expected = match kind {
+                "type" => 3,
+                "subtype" => 4,
+                _ => {
+                    return Err(CsvError::new(
+                        line_no,
+                        column,
+                        CsvErrorKind::InvalidEntry(kind.into()),
+                    ))
+                }
+            };
+            if fields.len() > expected {
+                let (column, _) = fields[expected];
+                return Err(CsvError::new(line_no, column, CsvErrorKind::TooManyFields));
+            }
+
+            if kind == "type" {
+                let (_, name) = field(1, "name")?;
+                let type_value = value(field(2, "value")?)?;
+                registry.add_type(type_value, name);
+            } else {
+                let (column, type_name) = field(1, "type")?;
+                let partition_type = registry
+                    .find_type(type_name)
+                    .or_else(|| value((column, type_name)).ok().map(PartitionType::from))
+                    .ok_or_else(|| {
+                        CsvError::new(line_no, column, CsvErrorKind::InvalidType(type_name.into()))
+                    })?;
+                let (_, name) = field(2, "name")?;
+                let subtype_value = value(field(3, "value")?)?;
+                registry.add_subtype(&partition_type, subtype_value, name);
+            }
+        }
+
+        Ok(registry)
+    }
+
+    pub fn add_type(&mut self, value: u8, name: &str) {
+        self.types.push((value, name.to_owned()));
+    }
+
+    pub fn add_subtype(&mut self, partition_type: &PartitionType, value: u8, name: &str) {
+        self.subtypes
+            .push((partition_type.value(), value, name.to_owned()));
+    }
+
+    /// The name of `partition_type`, either built in or registered
+    pub fn type_name(&self, partition_type: &PartitionType) -> Option<&str> {
+        partition_type.name().or_else(|| {
+            self.types
+                .iter()
+                .find(|(value, _)| *value == partition_type.value())
+                .map(|(_, name)| name.as_str())
+        })
+    }
+
+    /// The name of `subtype` of `partition_type`, either built in or registered
+    pub fn subtype_name(&self, partition_type: &PartitionType, subtype: &Subtype) -> Option<&str> {
+        subtype.name().or_else(|| {
+            self.subtypes
+                .iter()
+                .find(|(type_value, value, _)| {
+                    *type_value == partition_type.value() && *value == subtype.value()
+                })
+                .map(|(_, _, name)| name.as_str())
+        })
+    }
+
+    /// Look up a type by either its built in or registered name
+    pub fn find_type(&self, name: &str) -> Option<PartitionType> {
+        PartitionType::from_name(name).or_else(|| {
+            self.types
+                .iter()
+                .find(|(_, type_name)| type_name == name)
+                .map(|(value, _)| PartitionType::from(*value))
+        })
+    }
+
+    /// Look up a subtype of `partition_type` by either its built in or
+    /// registered name
+    pub fn find_subtype(&self, partition_type: &PartitionType, name: &str) -> Option<Subtype> {
+        Subtype::from_name(partition_type, name).or_else(|| {
+            self.subtypes
+                .iter()
+                .find(|(type_value, _, subtype_name)| {
+                    *type_value == partition_type.value() && subtype_name == name
+                })
+                .map(|(_, value, _)| Subtype::new(partition_type, *value))
+        })
+    }
+
+    /// Whether `value` is a registered type. Registered types below 0x40 are
+    /// parsed as `PartitionType::Invalid` as esp-idf reserves them.
+    pub fn has_type(&self, value: u8) -> bool {
+        self.types
+            .iter()
+            .any(|(type_value, _)| *type_value == value)
+    }
+
+    /// Whether `value` is a registered subtype of `partition_type`. Registered
+    /// subtypes of the app and data types are parsed as `Subtype::Invalid` as
+    /// esp-idf does not define them.
+    pub fn has_subtype(&self, partition_type: &PartitionType, value: u8) -> bool {
+        self.subtypes.iter().any(|(type_value, subtype_value, _)| {
+            *type_value == partition_type.value() && *subtype_value == value
+        })
+    }
+}
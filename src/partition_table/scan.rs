@@ -0,0 +1,145 @@
+use crate::partition_table::{PartitionTable, Severity, DATA_ALIGNMENT, DEFAULT_TABLE_OFFSET};
+
+/// The state of the MD5 record of a candidate table, ordered from least to most
+/// trustworthy
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Md5Status {
+    /// The MD5 record does not match the entries, the table is likely corrupt
+    Mismatch,
+    /// The table was generated with `--disable-md5sum`
+    Missing,
+    Valid,
+}
+
+/// A partition table found while scanning a binary along with where it was found
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    offset: u32,
+    table: PartitionTable,
+    errors: usize,
+}
+
+impl Candidate {
+    /// Offset of the table from the start of the scanned data
+    pub fn offset(&self) -> u32 {
+        self.offset
+    }
+
+    pub fn table(&self) -> &PartitionTable {
+        &self.table
+    }
+
+    pub fn into_table(self) -> PartitionTable {
+        self.table
+    }
+
+    pub fn md5_status(&self) -> Md5Status {
+        match self.table.md5_matches() {
+            Some(true) => Md5Status::Valid,
+            Some(false) => Md5Status::Mismatch,
+            None => Md5Status::Missing,
+        }
+    }
+
+    /// The number of errors found validating the table at its offset
+    pub fn errors(&self) -> usize {
+        self.errors
+    }
+}
+
+/// Find every table starting on a 4K boundary. Candidates are ranked by their MD5
+/// record, then by the number of validation errors, preferring the default offset
+/// and then lower offsets when those are equal.
+pub(crate) fn scan(input: &[u8]) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = (0..input.len())
+        .step_by(DATA_ALIGNMENT as usize)
+        .filter(|offset| input[*offset..].starts_with(&[0xaa, 0x50]))
+        .filter_map(|offset| {
+            let table = PartitionTable::parse(&input[offset..]).ok()?;
+            let errors = table
+                .validate_with_offset(offset as u32, None)
+                .iter()
+                .filter(|diagnostic| diagnostic.severity() == Severity::Error)
+                .count();
+
+            Some(Candidate {
+                offset: offset as u32,
+                table,
+                errors,
+            })
+        })
+        .collect();
+
+    candidates.sort_by_key(|candidate| {
+        (
+            std::cmp::Reverse(candidate.md5_status()),
+            candidate.errors,
+            candidate.offset != DEFAULT_TABLE_OFFSET,
+            candidate.offset,
+        )
+    });
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::partition_table::{PartitionSpec, PartitionType, Subtype};
+
+    fn place(dump: &mut [u8], offset: usize, table: &[u8]) {
+        dump[offset..offset + table.len()].copy_from_slice(table);
+    }
+
+    #[test]
+    fn candidates_are_ranked() {
+        let specs = [
+            PartitionSpec::new("nvs", PartitionType::Data, Subtype::DataNvs, 0x4000),
+            PartitionSpec::new("factory", PartitionType::App, Subtype::AppFactory, 0x20000),
+        ];
+        let table = PartitionTable::plan(&specs, 0x100000).unwrap();
+        let valid = table.to_bytes();
+        let unsigned = table.to_bytes_with_md5(None);
+        let mut mismatch = valid.clone();
+        // the digest follows the two entries and the start of the md5 record
+        mismatch[2 * 32 + 16] ^= 0xff;
+
+        let mut dump = vec![0xff; 0x40000];
+        place(&mut dump, 0x3000, &unsigned);
+        place(&mut dump, 0x8000, &valid);
+        // the partitions overlap a table at this offset
+        place(&mut dump, 0xa000, &valid);
+        place(&mut dump, 0x31000, &mismatch);
+        place(&mut dump, 0x30000, &mismatch);
+        // only tables on a 4K boundary are candidates
+        place(&mut dump, 0x20800, &valid);
+
+        let found: Vec<(u32, Md5Status)> = scan(&dump)
+            .iter()
+            .map(|candidate| (candidate.offset(), candidate.md5_status()))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (0x8000, Md5Status::Valid),
+                (0xa000, Md5Status::Valid),
+                (0x3000, Md5Status::Missing),
+                (0x30000, Md5Status::Mismatch),
+                (0x31000, Md5Status::Mismatch),
+            ]
+        );
+
+        let candidates = scan(&dump);
+        assert_eq!(candidates[0].errors(), 0);
+        assert!(candidates[1].errors() > 0);
+        assert_eq!(candidates[0].table().partitions().len(), 2);
+    }
+
+    #[test]
+    fn nothing_found() {
+        assert!(scan(&[]).is_empty());
+        // the magic alone doesn't make a table
+        let mut dump = vec![0u8; 0x2000];
+        dump[0x1000..0x1002].copy_from_slice(&[0xaa, 0x50]);
+        assert!(scan(&dump).is_empty());
+    }
+}
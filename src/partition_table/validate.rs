@@ -0,0 +1,485 @@
+use std::collections::HashMap;
+use std::fmt::Formatter;
+
+use crate::partition_table::{
+    IdfVersion, NameRegistry, Partition, PartitionType, Subtype, APP_ALIGNMENT, DATA_ALIGNMENT,
+    MAX_PARTITIONS, TABLE_REGION_SIZE,
+};
+
+/// Size the otadata partition must be, it holds two 4K sectors
+const OTADATA_SIZE: u32 = 0x2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    /// The table will work but is likely not what was intended
+    Warning,
+    /// The table is rejected by gen_esp32part.py or will not work on a device
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Warning => write!(f, "warning"),
+            Self::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A single problem found while validating a partition table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    severity: Severity,
+    kind: DiagnosticKind,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, kind: DiagnosticKind) -> Diagnostic {
+        Diagnostic { severity, kind }
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn kind(&self) -> &DiagnosticKind {
+        &self.kind
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.severity, self.kind)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DiagnosticKind {
+    /// More partitions than fit in the binary table
+    TooManyPartitions {
+        count: usize,
+    },
+    /// The partition starts inside the bootloader or partition table region
+    OverlapsTable {
+        name: String,
+        offset: u32,
+    },
+    Overlap {
+        first: String,
+        second: String,
+    },
+    /// App partitions must be 64K aligned so they can be mapped into memory
+    MisalignedApp {
+        name: String,
+        offset: u32,
+    },
+    /// Data partitions must be aligned to the 4K flash sector size
+    MisalignedData {
+        name: String,
+        offset: u32,
+    },
+    /// The size is not a multiple of the 4K flash sector size
+    MisalignedSize {
+        name: String,
+        size: u32,
+    },
+    DuplicateName {
+        name: String,
+    },
+    /// Names are stored in 16 bytes in the binary table and will be truncated
+    NameTooLong {
+        name: String,
+    },
+    InvalidType {
+        name: String,
+        value: u8,
+    },
+    InvalidSubtype {
+        name: String,
+        value: u8,
+    },
+    MultipleOtaData {
+        count: usize,
+    },
+    /// There are OTA app slots but no otadata partition to select between them
+    MissingOtaData,
+    /// There is an otadata partition but no OTA app slots for it to select
+    UnusedOtaData,
+    OtaDataSize {
+        size: u32,
+    },
+    NoApp,
+    /// The partition ends past the end of the flash
+    BeyondFlash {
+        name: String,
+        end: u64,
+        flash_size: u32,
+    },
+    /// NVS partitions are never encrypted by flash encryption, nvs encryption
+    /// uses the keys in an nvs_keys partition instead
+    EncryptedNvs {
+        name: String,
+    },
+    /// The nvs_keys partition is only protected when flash encryption is used
+    UnencryptedNvsKeys {
+        name: String,
+    },
+    /// The type or subtype is not supported by the targeted esp-idf release
+    RequiresIdfVersion {
+        name: String,
+        version: IdfVersion,
+    },
+}
+
+impl std::fmt::Display for DiagnosticKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TooManyPartitions { count } => write!(
+                f,
+                "table has {} partitions, the maximum is {}",
+                count, MAX_PARTITIONS
+            ),
+            Self::OverlapsTable { name, offset } => write!(
+                f,
+                "partition {} at {:#x} overlaps the bootloader or partition table",
+                name, offset
+            ),
+            Self::Overlap { first, second } => {
+                write!(f, "partitions {} and {} overlap", first, second)
+            }
+            Self::MisalignedApp { name, offset } => write!(
+                f,
+                "app partition {} at {:#x} is not aligned to {:#x}",
+                name, offset, APP_ALIGNMENT
+            ),
+            Self::MisalignedData { name, offset } => write!(
+                f,
+                "partition {} at {:#x} is not aligned to {:#x}",
+                name, offset, DATA_ALIGNMENT
+            ),
+            Self::MisalignedSize { name, size } => write!(
+                f,
+                "partition {} size {:#x} is not a multiple of {:#x}",
+                name, size, DATA_ALIGNMENT
+            ),
+            Self::DuplicateName { name } => {
+                write!(f, "partition name {} is used more than once", name)
+            }
+            Self::NameTooLong { name } => {
+                write!(f, "partition name {} is longer than 16 bytes", name)
+            }
+            Self::InvalidType { name, value } => {
+                write!(f, "partition {} has unknown type {:#x}", name, value)
+            }
+            Self::InvalidSubtype { name, value } => {
+                write!(f, "partition {} has unknown subtype {:#x}", name, value)
+            }
+            Self::MultipleOtaData { count } => {
+                write!(f, "found {} otadata partitions, only one is allowed", count)
+            }
+            Self::MissingOtaData => write!(f, "OTA app partitions require an otadata partition"),
+            Self::UnusedOtaData => write!(f, "otadata partition without any OTA app partitions"),
+            Self::OtaDataSize { size } => write!(
+                f,
+                "otadata partition size {:#x} should be {:#x}",
+                size, OTADATA_SIZE
+            ),
+            Self::NoApp => write!(f, "table does not have an app partition to boot"),
+            Self::BeyondFlash {
+                name,
+                end,
+                flash_size,
+            } => write!(
+                f,
+                "partition {} ends at {:#x} past the end of the {:#x} byte flash",
+                name, end, flash_size
+            ),
+            Self::EncryptedNvs { name } => write!(
+                f,
+                "nvs partition {} has the encrypted flag which is ignored",
+                name
+            ),
+            Self::UnencryptedNvsKeys { name } => write!(
+                f,
+                "nvs_keys partition {} should have the encrypted flag",
+                name
+            ),
+            Self::RequiresIdfVersion { name, version } => write!(
+                f,
+                "partition {} requires esp-idf {} or newer",
+                name, version
+            ),
+        }
+    }
+}
+
+/// Check a list of partitions for the problems gen_esp32part.py would reject
+/// along with other mistakes that result in a table that won't work as expected.
+/// Types and subtypes newer than `idf_version` are warned about when it is given,
+/// custom types and subtypes registered in `registry` are not reported as invalid.
+pub(crate) fn validate(
+    partitions: &[Partition],
+    table_offset: u32,
+    flash_size: Option<u32>,
+    idf_version: Option<IdfVersion>,
+    registry: &NameRegistry,
+) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut error = |kind| diagnostics.push(Diagnostic::new(Severity::Error, kind));
+
+    if partitions.len() > MAX_PARTITIONS {
+        error(DiagnosticKind::TooManyPartitions {
+            count: partitions.len(),
+        });
+    }
+
+    let reserved_end = table_offset as u64 + TABLE_REGION_SIZE as u64;
+    let mut names: HashMap<&str, usize> = HashMap::new();
+    for partition in partitions {
+        let name = partition.name.clone();
+        let count = names.entry(partition.name.as_str()).or_insert(0);
+        *count += 1;
+        if *count == 2 {
+            error(DiagnosticKind::DuplicateName { name: name.clone() });
+        }
+
+        if partition.name.len() > 16 {
+            error(DiagnosticKind::NameTooLong { name: name.clone() });
+        }
+
+        if let PartitionType::Invalid(value) = partition.partition_type {
+            if !registry.has_type(value) {
+                error(DiagnosticKind::InvalidType {
+                    name: name.clone(),
+                    value,
+                });
+            }
+        }
+
+        if let Subtype::Invalid(value) = partition.subtype {
+            if !matches!(partition.partition_type, PartitionType::Invalid(_))
+                && !registry.has_subtype(&partition.partition_type, value)
+            {
+                error(DiagnosticKind::InvalidSubtype {
+                    name: name.clone(),
+                    value,
+                });
+            }
+        }
+
+        // bootloader and partition_table partitions describe the reserved regions
+        let reserved = matches!(
+            partition.partition_type,
+            PartitionType::Bootloader | PartitionType::PartitionTable
+        );
+        if (partition.offset as u64) < reserved_end && !reserved {
+            error(DiagnosticKind::OverlapsTable {
+                name: name.clone(),
+                offset: partition.offset,
+            });
+        }
+
+        match partition.partition_type {
+            PartitionType::App if partition.offset % APP_ALIGNMENT != 0 => {
+                error(DiagnosticKind::MisalignedApp {
+                    name: name.clone(),
+                    offset: partition.offset,
+                })
+            }
+            _ if partition.offset % DATA_ALIGNMENT != 0 => error(DiagnosticKind::MisalignedData {
+                name: name.clone(),
+                offset: partition.offset,
+            }),
+            _ => {}
+        }
+
+        let end = partition.end();
+        if let Some(flash_size) = flash_size {
+            if end > flash_size as u64 {
+                error(DiagnosticKind::BeyondFlash {
+                    name,
+                    end,
+                    flash_size,
+                });
+            }
+        }
+    }
+
+    let mut sorted: Vec<&Partition> = partitions.iter().collect();
+    sorted.sort_by_key(|partition| partition.offset);
+    for (i, first) in sorted.iter().enumerate() {
+        let first_end = first.end();
+        for second in sorted[i + 1..].iter() {
+            if second.offset as u64 >= first_end {
+                break;
+            }
+
+            error(DiagnosticKind::Overlap {
+                first: first.name.clone(),
+                second: second.name.clone(),
+            });
+        }
+    }
+
+    let otadata: Vec<&Partition> = partitions
+        .iter()
+        .filter(|partition| partition.subtype == Subtype::DataOta)
+        .collect();
+    let has_ota_apps = partitions
+        .iter()
+        .any(|partition| partition.subtype.ota_slot().is_some());
+
+    if otadata.len() > 1 {
+        error(DiagnosticKind::MultipleOtaData {
+            count: otadata.len(),
+        });
+    }
+
+    if has_ota_apps && otadata.is_empty() {
+        error(DiagnosticKind::MissingOtaData);
+    }
+
+    for partition in &otadata {
+        if partition.size != OTADATA_SIZE {
+            error(DiagnosticKind::OtaDataSize {
+                size: partition.size,
+            });
+        }
+    }
+
+    let mut warning = |kind| diagnostics.push(Diagnostic::new(Severity::Warning, kind));
+
+    if !has_ota_apps && !otadata.is_empty() {
+        warning(DiagnosticKind::UnusedOtaData);
+    }
+
+    if !partitions
+        .iter()
+        .any(|partition| partition.partition_type == PartitionType::App)
+    {
+        warning(DiagnosticKind::NoApp);
+    }
+
+    for partition in partitions {
+        if partition.size % DATA_ALIGNMENT != 0 {
+            warning(DiagnosticKind::MisalignedSize {
+                name: partition.name.clone(),
+                size: partition.size,
+            });
+        }
+
+        let encrypted = partition.is_encrypted();
+        match partition.subtype {
+            Subtype::DataNvs if encrypted => warning(DiagnosticKind::EncryptedNvs {
+                name: partition.name.clone(),
+            }),
+            Subtype::DataNvsKeys if !encrypted => warning(DiagnosticKind::UnencryptedNvsKeys {
+                name: partition.name.clone(),
+            }),
+            _ => {}
+        }
+
+        let required = partition
+            .partition_type
+            .min_idf_version()
+            .max(partition.subtype.min_idf_version());
+        if let (Some(required), Some(idf_version)) = (required, idf_version) {
+            if required > idf_version {
+                warning(DiagnosticKind::RequiresIdfVersion {
+                    name: partition.name.clone(),
+                    version: required,
+                });
+            }
+        }
+    }
+
+    diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::partition_table::{
+        DiagnosticKind, IdfVersion, NameRegistry, PartitionTable, DEFAULT_TABLE_OFFSET,
+    };
+
+    fn invalid(table: &PartitionTable, names: &NameRegistry) -> Vec<String> {
+        table
+            .validate_with_names(DEFAULT_TABLE_OFFSET, None, None, names)
+            .iter()
+            .filter_map(|diagnostic| match diagnostic.kind() {
+                DiagnosticKind::InvalidType { name, .. }
+                | DiagnosticKind::InvalidSubtype { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn registered_names_are_not_invalid() {
+        let table = PartitionTable::from_csv(
+            "sensors,0x10,0x1,0x9000,4K\n\
+             logs,data,0x40,0xa000,4K\n\
+             other,data,0x41,0xb000,4K\n",
+        )
+        .unwrap();
+        assert_eq!(
+            invalid(&table, &NameRegistry::new()),
+            ["sensors", "logs", "other"]
+        );
+
+        let names =
+            NameRegistry::parse("type, sensors, 0x10\nsubtype, data, logs, 0x40\n").unwrap();
+        assert_eq!(invalid(&table, &names), ["other"]);
+    }
+
+    #[test]
+    fn newer_types_require_an_idf_version() {
+        let table = PartitionTable::from_csv(
+            "nvs,data,nvs,0x9000,16K\n\
+             keys,data,nvs_keys,0xd000,4K,encrypted\n\
+             raw,data,undefined,0xe000,4K\n\
+             fs,data,littlefs,0x10000,64K\n\
+             tee,app,tee_0,0x20000,64K\n\
+             recovery,bootloader,recovery,0x30000,64K\n",
+        )
+        .unwrap();
+        let required = |version: &str| -> Vec<String> {
+            table
+                .validate_for_idf_version(
+                    DEFAULT_TABLE_OFFSET,
+                    None,
+                    IdfVersion::parse(version).unwrap(),
+                )
+                .iter()
+                .filter_map(|diagnostic| match diagnostic.kind() {
+                    DiagnosticKind::RequiresIdfVersion { name, version } => {
+                        Some(format!("{} {}", name, version))
+                    }
+                    _ => None,
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            required("v3.3"),
+            [
+                "keys v4.0",
+                "raw v4.4",
+                "fs v5.3",
+                "tee v5.5",
+                "recovery v5.5"
+            ]
+        );
+        assert_eq!(required("4.4.7"), ["fs v5.3", "tee v5.5", "recovery v5.5"]);
+        assert_eq!(required("5.3"), ["tee v5.5", "recovery v5.5"]);
+        assert!(required("v5.5").is_empty());
+        assert!(required("6").is_empty());
+
+        assert!(!table.validate(None).iter().any(|diagnostic| matches!(
+            diagnostic.kind(),
+            DiagnosticKind::RequiresIdfVersion { .. }
+        )));
+        assert_eq!(IdfVersion::parse("v5"), Some(IdfVersion::new(5, 0)));
+        assert_eq!(IdfVersion::parse("five"), None);
+    }
+}